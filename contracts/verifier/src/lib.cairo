@@ -1,5 +1,6 @@
 pub mod groth16_verifier;
 mod groth16_verifier_constants;
+pub mod mock_groth16_verifier;
 pub mod universal_ecip;
 pub mod world_relayer_verifier;
 use core::num::traits::{Bounded, WideMul};
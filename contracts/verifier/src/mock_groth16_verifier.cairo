@@ -0,0 +1,26 @@
+/// Test-only stand-in for `Risc0Groth16VerifierBN254`.
+///
+/// Accepts any calldata and returns it as the journal, one byte per felt. Used
+/// with the relayer `dev` and `mock` provers to run relays without Groth16
+/// proving. NEVER deploy it in front of a production store.
+#[starknet::contract]
+mod MockRisc0Groth16Verifier {
+    #[storage]
+    struct Storage {}
+
+    #[abi(embed_v0)]
+    impl IRisc0Groth16VerifierBN254 of verifier::groth16_verifier::IRisc0Groth16VerifierBN254<
+        ContractState,
+    > {
+        fn verify_groth16_proof_bn254(
+            self: @ContractState, full_proof_with_hints: Span<felt252>,
+        ) -> Option<Span<u8>> {
+            let mut journal = array![];
+            for byte in full_proof_with_hints {
+                journal.append((*byte).try_into().expect('Journal byte out of range'));
+            };
+
+            Option::Some(journal.span())
+        }
+    }
+}
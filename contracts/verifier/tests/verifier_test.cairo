@@ -69,3 +69,39 @@ fn test_get_world_relayer_store_address() {
     let store_address = verifier.get_world_relayer_store_address();
     assert!(store_address != starknet::contract_address_const::<0>());
 }
+
+
+#[test]
+fn test_verify_latest_root_proof_with_mock_verifier() {
+    let (mock_address, _) = declare("MockRisc0Groth16Verifier")
+        .unwrap()
+        .contract_class()
+        .deploy(@array![])
+        .unwrap();
+    let (store_address, _) = declare("WorldRelayerStore")
+        .unwrap()
+        .contract_class()
+        .deploy(@array![])
+        .unwrap();
+    let (verifier_address, _) = declare("WorldRelayerVerifier")
+        .unwrap()
+        .contract_class()
+        .deploy(@array![mock_address.into(), store_address.into()])
+        .unwrap();
+    let store = IWorldRelayerStoreDispatcher { contract_address: store_address };
+    store.initialize(verifier_address);
+
+    // Journal of block 21891875, preceded by the felt the verifier drops
+    let proof = array![
+        44, 35, 11, 78, 1, 0, 0, 0, 0, 32, 0, 0, 0, 38, 196, 138, 22, 71, 45, 64, 179, 97, 81, 85,
+        93, 19, 63, 51, 139, 240, 221, 140, 41, 94, 168, 193, 21, 133, 129, 232, 74, 11, 109, 254,
+        243,
+    ];
+    let verifier = IWorldRelayerVerifierDispatcher { contract_address: verifier_address };
+    assert!(verifier.verify_latest_root_proof(proof.span()));
+
+    assert_eq!(
+        store.get_latest_root_block(),
+        (17535143312471158466661076185880618200719072926547797113181234216764225486579, 21891875),
+    );
+}
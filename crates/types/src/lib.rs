@@ -1,5 +1,6 @@
-use alloy_consensus::Header;
+use alloy_consensus::{Header, Sealable};
 use alloy_primitives::{FixedBytes, U256};
+use error::ProverError;
 use header::RlpHeader;
 use proofs::AccountProof;
 use serde::{Deserialize, Serialize};
@@ -12,7 +13,7 @@ pub mod proofs;
 ///
 /// Contains all required data to prove the existence of a storage slot
 /// within a specific Ethereum block.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ProverInput {
     /// Block header wrapped with RLP encoding/decoding support
     ///
@@ -31,6 +32,28 @@ pub struct ProverInput {
     pub account_proof: AccountProof,
 }
 
+impl ProverInput {
+    /// Checks the block header, then the account proof against its state
+    /// root and the storage proof against the account storage root.
+    ///
+    /// The guest program panics when this fails, the host runs it natively
+    /// to reject an invalid input before spending a proving attempt on it.
+    pub fn verify(&self) -> Result<(), ProverError> {
+        let block_header = self.header.hash_slow();
+        if self.block_header != block_header {
+            return Err(ProverError::BlockHashMismatch {
+                expected: self.block_header,
+                found: block_header,
+            });
+        }
+
+        self.account_proof.verify_proof(self.header.state_root)?;
+        self.account_proof
+            .storage_proof
+            .verify_proof(self.account_proof.trie.storage_root)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ProverOutput {
     pub block_number: u64,
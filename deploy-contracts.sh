@@ -68,22 +68,35 @@ RELAYER_STORE_ADDRESS=$(starkli deploy $RELAYER_STORE_HASH \
     --strk -w | grep -o '0x[a-fA-F0-9]\{64\}' | head -1)
 echo -e "${GREEN}Contract address: ${BOLD}$RELAYER_STORE_ADDRESS${NC}\n"
 
-# Universal ECIP contract
-echo -e "${YELLOW}Declaring Universal ECIP contract...${NC}"
-ECIP_HASH=$(starkli declare --compiler-version 2.9.1 ./target/dev/verifier_UniversalECIP.contract_class.json \
-    --strk -w | grep -o '0x[a-fA-F0-9]\{64\}' | head -1)
-echo -e "${GREEN}Class hash declared: ${BOLD}$ECIP_HASH${NC}\n"
-
-# Groth16 Verifier contract
-echo -e "${YELLOW}Declaring Groth16 Verifier contract...${NC}"
-VERIFIER_HASH=$(starkli declare --compiler-version 2.9.1 ./target/dev/verifier_Risc0Groth16VerifierBN254.contract_class.json \
-    --strk -w | grep -o '0x[a-fA-F0-9]\{64\}' | head -1)
-echo -e "${GREEN}Class hash declared: ${BOLD}$VERIFIER_HASH${NC}\n"
-
-echo -e "${YELLOW}Deploying Groth16 Verifier contract...${NC}"
-VERIFIER_ADDRESS=$(starkli deploy $VERIFIER_HASH $ECIP_HASH \
-    --strk -w | grep -o '0x[a-fA-F0-9]\{64\}' | head -1)
-echo -e "${GREEN}Contract deployed at: ${BOLD}$VERIFIER_ADDRESS${NC}\n"
+if [ "$MOCK_VERIFIER" = "true" ]; then
+    # Test-only verifier accepting the calldata of the relayer dev and mock provers
+    echo -e "${YELLOW}Declaring Mock Groth16 Verifier contract...${NC}"
+    VERIFIER_HASH=$(starkli declare --compiler-version 2.9.1 ./target/dev/verifier_MockRisc0Groth16Verifier.contract_class.json \
+        --strk -w | grep -o '0x[a-fA-F0-9]\{64\}' | head -1)
+    echo -e "${GREEN}Class hash declared: ${BOLD}$VERIFIER_HASH${NC}\n"
+
+    echo -e "${YELLOW}Deploying Mock Groth16 Verifier contract...${NC}"
+    VERIFIER_ADDRESS=$(starkli deploy $VERIFIER_HASH \
+        --strk -w | grep -o '0x[a-fA-F0-9]\{64\}' | head -1)
+    echo -e "${GREEN}Contract deployed at: ${BOLD}$VERIFIER_ADDRESS${NC}\n"
+else
+    # Universal ECIP contract
+    echo -e "${YELLOW}Declaring Universal ECIP contract...${NC}"
+    ECIP_HASH=$(starkli declare --compiler-version 2.9.1 ./target/dev/verifier_UniversalECIP.contract_class.json \
+        --strk -w | grep -o '0x[a-fA-F0-9]\{64\}' | head -1)
+    echo -e "${GREEN}Class hash declared: ${BOLD}$ECIP_HASH${NC}\n"
+
+    # Groth16 Verifier contract
+    echo -e "${YELLOW}Declaring Groth16 Verifier contract...${NC}"
+    VERIFIER_HASH=$(starkli declare --compiler-version 2.9.1 ./target/dev/verifier_Risc0Groth16VerifierBN254.contract_class.json \
+        --strk -w | grep -o '0x[a-fA-F0-9]\{64\}' | head -1)
+    echo -e "${GREEN}Class hash declared: ${BOLD}$VERIFIER_HASH${NC}\n"
+
+    echo -e "${YELLOW}Deploying Groth16 Verifier contract...${NC}"
+    VERIFIER_ADDRESS=$(starkli deploy $VERIFIER_HASH $ECIP_HASH \
+        --strk -w | grep -o '0x[a-fA-F0-9]\{64\}' | head -1)
+    echo -e "${GREEN}Contract deployed at: ${BOLD}$VERIFIER_ADDRESS${NC}\n"
+fi
 
 echo -e "${YELLOW}Declaring World Relayer Verifier contract...${NC}"
 WORLD_RELAYER_VERIFIER_HASH=$(starkli declare --compiler-version 2.9.1 ./target/dev/verifier_WorldRelayerVerifier.contract_class.json \
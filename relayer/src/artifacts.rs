@@ -0,0 +1,154 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use alloy::primitives::keccak256;
+use eyre::{Result, WrapErr};
+use methods::STORAGE_INCLUSION_ID;
+use risc0_zkvm::sha::Digest;
+use types::ProverInput;
+
+use crate::{prover::ProofArtifact, util::write_atomic};
+
+/// Proof artifacts saved as `<dir>/<block_number>/<digest>.json`.
+///
+/// The digest addresses the prover input, the guest image and the kind of
+/// proof, so an artifact is only reused for the exact same proving job.
+#[derive(Debug, Clone)]
+pub struct ArtifactStore {
+    dir: PathBuf,
+}
+
+impl ArtifactStore {
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Load the artifact proving the input along with its path, `None` if it
+    /// was never proven
+    pub fn load(
+        &self,
+        kind: &str,
+        input: &ProverInput,
+    ) -> Result<Option<(PathBuf, ProofArtifact)>> {
+        let path = self.path(kind, input)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let artifact = self.read(&path)?;
+
+        Ok(Some((path, artifact)))
+    }
+
+    /// Read the artifact saved at a path
+    pub fn read(&self, path: &Path) -> Result<ProofArtifact> {
+        let content = fs::read(path)
+            .wrap_err_with(|| format!("Failed to read proof artifact {}", path.display()))?;
+
+        serde_json::from_slice(&content)
+            .wrap_err_with(|| format!("Invalid proof artifact {}", path.display()))
+    }
+
+    /// Atomically save the artifact proving the input, returning its path
+    pub fn save(
+        &self,
+        kind: &str,
+        input: &ProverInput,
+        artifact: &ProofArtifact,
+    ) -> Result<PathBuf> {
+        let path = self.path(kind, input)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).wrap_err_with(|| {
+                format!("Failed to create artifact directory {}", parent.display())
+            })?;
+        }
+
+        write_atomic(&path, &serde_json::to_vec(artifact)?)
+            .wrap_err("Failed to save the proof artifact")?;
+
+        Ok(path)
+    }
+
+    fn path(&self, kind: &str, input: &ProverInput) -> Result<PathBuf> {
+        let mut content = kind.as_bytes().to_vec();
+        content.extend_from_slice(Digest::from(STORAGE_INCLUSION_ID).as_bytes());
+        content.extend(serde_json::to_vec(input)?);
+        let digest = keccak256(content);
+
+        Ok(self
+            .dir
+            .join(input.header.number.to_string())
+            .join(format!("{digest:x}.json")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::{
+        primitives::{B256, U256},
+        rpc::types::{EIP1186AccountProofResponse, EIP1186StorageProof},
+    };
+    use types::{header::RlpHeader, proofs::AccountProof};
+
+    use super::*;
+
+    fn input(root: u64) -> ProverInput {
+        let account_proof = EIP1186AccountProofResponse {
+            storage_proof: vec![EIP1186StorageProof {
+                value: U256::from(root),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        ProverInput {
+            header: RlpHeader::new(Default::default()),
+            block_header: B256::ZERO,
+            account_proof: AccountProof::from(account_proof),
+        }
+    }
+
+    fn artifact(root: u64) -> ProofArtifact {
+        ProofArtifact {
+            block_number: 0,
+            root: U256::from(root),
+            image_id: Digest::from(STORAGE_INCLUSION_ID),
+            journal: vec![1, 2, 3],
+            seal: Vec::new(),
+            receipt: None,
+            calldata: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn saved_artifact_is_loaded_for_same_job_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = ArtifactStore::new(dir.path());
+        assert!(store.load("mock", &input(1)).unwrap().is_none());
+
+        let path = store.save("mock", &input(1), &artifact(1)).unwrap();
+        let (loaded_path, loaded) = store.load("mock", &input(1)).unwrap().unwrap();
+
+        assert_eq!(loaded_path, path);
+        assert_eq!(
+            (loaded.root, loaded.journal),
+            (U256::from(1), vec![1, 2, 3])
+        );
+        assert!(store.load("groth16", &input(1)).unwrap().is_none());
+        assert!(store.load("mock", &input(2)).unwrap().is_none());
+    }
+
+    #[test]
+    fn corrupt_artifact_fails_to_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = ArtifactStore::new(dir.path());
+        let path = store.save("mock", &input(1), &artifact(1)).unwrap();
+        fs::write(&path, b"{").unwrap();
+
+        assert!(store.load("mock", &input(1)).is_err());
+        assert!(store.read(&path).is_err());
+    }
+}
@@ -0,0 +1,91 @@
+use std::fmt;
+
+use crate::listener::RootChange;
+
+/// Last stage reached by a root change during a backfill
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackfillStage {
+    Detected,
+    /// A later change of the same block replaced the root
+    Superseded,
+    InputPrepared,
+    Proven,
+    Published,
+    /// Every destination already held the root or a newer one
+    Skipped,
+}
+
+impl fmt::Display for BackfillStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Detected => write!(f, "detected"),
+            Self::Superseded => write!(f, "superseded"),
+            Self::InputPrepared => write!(f, "input prepared"),
+            Self::Proven => write!(f, "proven"),
+            Self::Published => write!(f, "published"),
+            Self::Skipped => write!(f, "skipped"),
+        }
+    }
+}
+
+/// Outcome of a single root change during a backfill
+#[derive(Debug, Clone)]
+pub struct BackfillEntry {
+    pub change: RootChange,
+    pub stage: BackfillStage,
+    /// Error that stopped the root change before the requested stage
+    pub error: Option<String>,
+}
+
+/// Report of a backfill over an explicit block range
+#[derive(Debug, Clone)]
+pub struct BackfillReport {
+    pub from_block: u64,
+    pub to_block: u64,
+    pub entries: Vec<BackfillEntry>,
+}
+
+impl BackfillReport {
+    pub fn new(from_block: u64, to_block: u64) -> Self {
+        Self {
+            from_block,
+            to_block,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Number of root changes that failed before the requested stage
+    pub fn failures(&self) -> usize {
+        self.entries.iter().filter(|e| e.error.is_some()).count()
+    }
+}
+
+impl fmt::Display for BackfillReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Backfill of blocks {} to {}: {} root changes, {} failed",
+            self.from_block,
+            self.to_block,
+            self.entries.len(),
+            self.failures()
+        )?;
+
+        for entry in &self.entries {
+            write!(
+                f,
+                "  block {} log {} root {:#x}: {}",
+                entry.change.block_number,
+                entry.change.log_index,
+                entry.change.post_root,
+                entry.stage
+            )?;
+            match &entry.error {
+                Some(error) => writeln!(f, " (failed: {error})")?,
+                None => writeln!(f)?,
+            }
+        }
+
+        Ok(())
+    }
+}
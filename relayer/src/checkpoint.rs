@@ -0,0 +1,114 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+
+use crate::{listener::RootChange, util::write_atomic};
+
+/// Position of the last fully processed `TreeChanged` log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub block_number: u64,
+    pub log_index: u64,
+}
+
+impl Checkpoint {
+    /// Checkpoint marking every block before `block_number` as processed
+    pub fn before_block(block_number: u64) -> Self {
+        Self {
+            block_number: block_number.saturating_sub(1),
+            log_index: u64::MAX,
+        }
+    }
+
+    /// Whether the root change comes after this checkpoint
+    pub fn precedes(&self, change: &RootChange) -> bool {
+        (change.block_number, change.log_index) > (self.block_number, self.log_index)
+    }
+}
+
+impl From<&RootChange> for Checkpoint {
+    fn from(change: &RootChange) -> Self {
+        Self {
+            block_number: change.block_number,
+            log_index: change.log_index,
+        }
+    }
+}
+
+/// Listener checkpoint persisted as a JSON state file
+#[derive(Debug, Clone)]
+pub struct CheckpointStore {
+    path: PathBuf,
+}
+
+impl CheckpointStore {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Load the stored checkpoint, `None` if the relayer never processed a log
+    pub fn load(&self) -> Result<Option<Checkpoint>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(&self.path)
+            .wrap_err_with(|| format!("Failed to read state file {}", self.path.display()))?;
+        let checkpoint = serde_json::from_str(&content)
+            .wrap_err_with(|| format!("Invalid state file {}", self.path.display()))?;
+
+        Ok(Some(checkpoint))
+    }
+
+    /// Atomically replace the stored checkpoint
+    pub fn save(&self, checkpoint: &Checkpoint) -> Result<()> {
+        write_atomic(&self.path, &serde_json::to_vec_pretty(checkpoint)?)
+            .wrap_err("Failed to save the checkpoint")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{B256, U256};
+
+    use super::*;
+    use crate::listener::TreeChangeKind;
+
+    fn change(block_number: u64, log_index: u64) -> RootChange {
+        RootChange {
+            kind: TreeChangeKind::Insertion,
+            pre_root: U256::ZERO,
+            post_root: U256::from(1),
+            block_number,
+            block_hash: None,
+            tx_hash: B256::ZERO,
+            log_index,
+        }
+    }
+
+    #[test]
+    fn precedes_later_logs_only() {
+        let checkpoint = Checkpoint::from(&change(10, 3));
+
+        assert!(!checkpoint.precedes(&change(9, 7)));
+        assert!(!checkpoint.precedes(&change(10, 2)));
+        assert!(!checkpoint.precedes(&change(10, 3)));
+        assert!(checkpoint.precedes(&change(10, 4)));
+        assert!(checkpoint.precedes(&change(11, 0)));
+    }
+
+    #[test]
+    fn before_block_precedes_the_whole_block() {
+        let checkpoint = Checkpoint::before_block(10);
+
+        assert!(!checkpoint.precedes(&change(9, u64::MAX)));
+        assert!(checkpoint.precedes(&change(10, 0)));
+        assert_eq!(Checkpoint::before_block(0).block_number, 0);
+    }
+}
@@ -0,0 +1,163 @@
+use std::time::Duration;
+
+use alloy::primitives::{B256, U256};
+use alloy_chains::Chain;
+use starknet::core::types::Felt;
+use thiserror::Error;
+use types::error::ProverError;
+
+/// Underlying error of a failed operation
+pub type Source = Box<dyn std::error::Error + Send + Sync>;
+
+/// Errors raised by the relayer, by the component they come from
+#[derive(Debug, Error)]
+pub enum RelayerError {
+    /// Detecting root changes on Ethereum
+    #[error(transparent)]
+    Listener(#[from] ListenerError),
+    /// Prover input rejected before proving
+    #[error(transparent)]
+    Input(#[from] InputError),
+    /// Executing or proving the guest
+    #[error("Prover error: {context}")]
+    Prover {
+        context: String,
+        #[source]
+        source: Source,
+    },
+    /// Proving exceeded its deadline
+    #[error("Proving timed out after {0:?}")]
+    Timeout(Duration),
+    /// Proving job abandoned before completion
+    #[error("Proving cancelled")]
+    Cancelled,
+    /// Encoding the seal or the verifier calldata
+    #[error("Calldata error: {context}")]
+    Calldata {
+        context: String,
+        #[source]
+        source: Source,
+    },
+    /// Sending the proof to Starknet
+    #[error(transparent)]
+    Publisher(#[from] PublisherError),
+}
+
+impl RelayerError {
+    pub fn prover(context: impl Into<String>, source: impl Into<Source>) -> Self {
+        Self::Prover {
+            context: context.into(),
+            source: source.into(),
+        }
+    }
+
+    pub fn calldata(context: impl Into<String>, source: impl Into<Source>) -> Self {
+        Self::Calldata {
+            context: context.into(),
+            source: source.into(),
+        }
+    }
+
+    /// Whether retrying the failed operation cannot succeed
+    pub fn is_permanent(&self) -> bool {
+        match self {
+            Self::Input(_) | Self::Calldata { .. } => true,
+            Self::Publisher(e) => e.is_permanent(),
+            _ => false,
+        }
+    }
+}
+
+impl From<ProverError> for RelayerError {
+    fn from(error: ProverError) -> Self {
+        Self::Input(error.into())
+    }
+}
+
+/// Ethereum data the listener cannot make sense of
+#[derive(Debug, Error)]
+pub enum ListenerError {
+    /// `latestRoot()` and the raw storage slot disagree, usually a wrong
+    /// `world_id_latest_root_slot`
+    #[error("latestRoot() {root:#x} differs from storage slot {slot} value {raw:#x} at block {block_number}")]
+    SlotMismatch {
+        block_number: u64,
+        slot: U256,
+        root: U256,
+        raw: U256,
+    },
+    /// Log returned without its block, the provider served a pending log
+    #[error("TreeChanged log of transaction {tx_hash:?} has no block number")]
+    MissingBlockNumber { tx_hash: Option<B256> },
+    /// Reorg replacing blocks older than the tracked ones
+    #[error("Chain reorg deeper than the tracked blocks at block {0}")]
+    DeepReorg(u64),
+}
+
+/// Prover input the guest would reject or that proves the wrong root
+#[derive(Debug, Error)]
+pub enum InputError {
+    /// Header, account or storage proof failing the guest checks
+    #[error("Invalid prover input: {0}")]
+    Verification(#[from] ProverError),
+    /// Storage value differing from the root it should attest to, usually a
+    /// wrong `world_id_latest_root_slot`
+    #[error("Proven root {proven:#x} differs from {expected_from} {expected:#x} at block {block_number}")]
+    RootMismatch {
+        block_number: u64,
+        /// Where the expected root comes from
+        expected_from: &'static str,
+        expected: U256,
+        proven: U256,
+    },
+    /// Guest journal committing to another block or root than the input
+    #[error("Journal commits to root {journal_root:#x} at block {journal_block}, expected root {root:#x} at block {block_number}")]
+    JournalMismatch {
+        block_number: u64,
+        root: U256,
+        journal_block: u64,
+        journal_root: U256,
+    },
+}
+
+/// Failures to deliver a proof to a Starknet destination
+#[derive(Debug, Error)]
+pub enum PublisherError {
+    #[error("Unsupported chain {0} for Starknet")]
+    UnsupportedChain(Chain),
+    /// Transaction rejected by a contract when estimating its fee, e.g. an
+    /// invalid proof or a store already holding a newer root
+    #[error("Transaction rejected by {destination}: {reason}")]
+    Rejected { destination: String, reason: String },
+    /// Transaction included in a block but reverted
+    #[error("Transaction {tx_hash:#x} reverted on {destination}: {reason}")]
+    Reverted {
+        destination: String,
+        tx_hash: Felt,
+        reason: String,
+    },
+    /// Transaction still not included after waiting for it
+    #[error("Transaction {tx_hash:#x} not confirmed on {destination} after {waited:?}")]
+    Unconfirmed {
+        destination: String,
+        tx_hash: Felt,
+        waited: Duration,
+    },
+    /// Request to the Starknet node failing
+    #[error("Starknet request to {destination} failed")]
+    Provider {
+        destination: String,
+        #[source]
+        source: Source,
+    },
+}
+
+impl PublisherError {
+    /// Whether sending the same transaction again cannot succeed
+    pub fn is_permanent(&self) -> bool {
+        matches!(
+            self,
+            Self::UnsupportedChain(_) | Self::Rejected { .. } | Self::Reverted { .. }
+        )
+    }
+}
@@ -0,0 +1,301 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use alloy::primitives::U256;
+use eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use starknet::core::types::Felt;
+use types::ProverInput;
+
+use crate::{checkpoint::Checkpoint, util::unix_timestamp};
+
+/// Log index of the jobs relaying the root read from the state of a block,
+/// which comes after every log of the block
+pub const BLOCK_STATE: u64 = u64::MAX;
+
+/// Lifecycle of a relay job, in pipeline order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JobState {
+    /// Root detected, nothing fetched yet
+    Detected,
+    /// Prover input fetched from Ethereum
+    InputFetched,
+    /// Proof generated
+    Proved,
+    /// Proof transactions sent to the Starknet destinations
+    Submitted,
+    /// Proof delivered to every Starknet destination
+    Confirmed,
+    /// Nothing to relay, only the checkpoint moves
+    Skipped,
+    /// A newer root was relayed instead
+    Superseded,
+    /// Dead-lettered after exhausting its retries
+    Failed,
+}
+
+impl JobState {
+    /// Whether no stage is left to run for the job
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            Self::Confirmed | Self::Skipped | Self::Superseded | Self::Failed
+        )
+    }
+}
+
+/// Progress of a proof towards one Starknet destination
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeliveryState {
+    #[default]
+    Pending,
+    Submitted,
+    Confirmed,
+    /// The destination already holds the root of a newer block
+    Skipped,
+    Failed,
+}
+
+/// Delivery of a proof to one Starknet destination
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Delivery {
+    pub state: DeliveryState,
+    pub tx_hash: Option<Felt>,
+    pub error: Option<String>,
+}
+
+/// Relay job of the root at a block, along with the artifacts of the stages
+/// completed so far.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub block_number: u64,
+    /// Index of the `TreeChanged` log in the block, [`BLOCK_STATE`] for
+    /// roots read from the block state
+    pub log_index: u64,
+    /// Root the proof must attest to, if known
+    pub root: Option<U256>,
+    /// Listener position completed once the job is finished
+    pub checkpoint: Option<Checkpoint>,
+    pub state: JobState,
+    pub input: Option<ProverInput>,
+    /// Path of the proof artifact, kept out of the store with its receipt
+    pub proof: Option<PathBuf>,
+    /// Deliveries by destination name
+    pub deliveries: BTreeMap<String, Delivery>,
+    /// Unix timestamp of the last state change
+    pub updated_at: u64,
+}
+
+impl JobRecord {
+    pub fn new(
+        block_number: u64,
+        log_index: u64,
+        root: Option<U256>,
+        checkpoint: Option<Checkpoint>,
+    ) -> Self {
+        Self {
+            block_number,
+            log_index,
+            root,
+            checkpoint,
+            state: JobState::Detected,
+            input: None,
+            proof: None,
+            deliveries: BTreeMap::new(),
+            updated_at: unix_timestamp(),
+        }
+    }
+
+    /// Delivery to a destination, pending if not started yet
+    pub fn delivery(&self, destination: &str) -> Delivery {
+        self.deliveries
+            .get(destination)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Restart a failed job from its last completed stage, keeping the
+    /// deliveries that went through
+    pub fn reopen(&mut self) {
+        self.state = if self.proof.is_some() {
+            JobState::Proved
+        } else if self.input.is_some() {
+            JobState::InputFetched
+        } else {
+            JobState::Detected
+        };
+        for delivery in self.deliveries.values_mut() {
+            if delivery.state == DeliveryState::Failed {
+                *delivery = Delivery::default();
+            }
+        }
+    }
+
+    /// Job that only advances the checkpoint, in order with the others
+    pub fn skipped(block_number: u64, checkpoint: Option<Checkpoint>) -> Self {
+        let log_index = checkpoint.map_or(BLOCK_STATE, |checkpoint| checkpoint.log_index);
+        Self {
+            state: JobState::Skipped,
+            ..Self::new(block_number, log_index, None, checkpoint)
+        }
+    }
+
+    /// Job of a root dropped in favor of a newer one
+    pub fn superseded(block_number: u64, log_index: u64, root: U256) -> Self {
+        Self {
+            state: JobState::Superseded,
+            ..Self::new(block_number, log_index, Some(root), None)
+        }
+    }
+}
+
+/// Relay jobs persisted in an embedded sled database, keyed by block number
+/// and log index.
+///
+/// sled locks its database for the lifetime of the process, the store is only
+/// opened on first use so that commands not touching jobs can run next to
+/// the relay service.
+#[derive(Debug, Clone)]
+pub struct JobStore {
+    path: PathBuf,
+    db: Arc<Mutex<Option<sled::Db>>>,
+}
+
+impl JobStore {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            db: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn get(&self, block_number: u64, log_index: u64) -> Result<Option<JobRecord>> {
+        self.db()?
+            .get(key(block_number, log_index))?
+            .map(|value| serde_json::from_slice(&value))
+            .transpose()
+            .wrap_err_with(|| {
+                format!("Invalid job record for block {block_number} log {log_index}")
+            })
+    }
+
+    /// Write the record through, stamping its update time
+    pub fn put(&self, record: &mut JobRecord) -> Result<()> {
+        record.updated_at = unix_timestamp();
+        self.db()?.insert(
+            key(record.block_number, record.log_index),
+            serde_json::to_vec(record)?,
+        )?;
+
+        Ok(())
+    }
+
+    /// Jobs with stages left to run, in block and log order
+    pub fn unfinished(&self) -> Result<Vec<JobRecord>> {
+        let mut records = Vec::new();
+        for entry in self.db()?.iter() {
+            let (_, value) = entry?;
+            let record: JobRecord = serde_json::from_slice(&value)?;
+            if !record.state.is_finished() {
+                records.push(record);
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Make sure every write reached the disk
+    pub async fn flush(&self) -> Result<()> {
+        self.db()?.flush_async().await?;
+
+        Ok(())
+    }
+
+    /// The database, opened on first use
+    fn db(&self) -> Result<sled::Db> {
+        let mut db = self.db.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(db) = db.as_ref() {
+            return Ok(db.clone());
+        }
+
+        let opened = sled::open(&self.path)
+            .wrap_err_with(|| format!("Failed to open job store {}", self.path.display()))?;
+        Ok(db.insert(opened).clone())
+    }
+}
+
+/// Big-endian key, iterating the store in block and log order
+fn key(block_number: u64, log_index: u64) -> [u8; 16] {
+    let mut key = [0; 16];
+    key[..8].copy_from_slice(&block_number.to_be_bytes());
+    key[8..].copy_from_slice(&log_index.to_be_bytes());
+
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reopen_resumes_from_last_completed_stage() {
+        let mut record = JobRecord::new(10, 0, None, None);
+        record.state = JobState::Failed;
+        record.reopen();
+        assert_eq!(record.state, JobState::Detected);
+
+        record.proof = Some(PathBuf::from("proof.json"));
+        record.deliveries.insert(
+            "mainnet".into(),
+            Delivery {
+                state: DeliveryState::Confirmed,
+                ..Default::default()
+            },
+        );
+        record.deliveries.insert(
+            "appchain".into(),
+            Delivery {
+                state: DeliveryState::Failed,
+                error: Some("reverted".into()),
+                ..Default::default()
+            },
+        );
+        record.state = JobState::Failed;
+        record.reopen();
+
+        assert_eq!(record.state, JobState::Proved);
+        assert_eq!(record.delivery("mainnet").state, DeliveryState::Confirmed);
+        assert_eq!(record.delivery("appchain").state, DeliveryState::Pending);
+        assert_eq!(record.delivery("appchain").error, None);
+    }
+
+    #[test]
+    fn store_iterates_in_block_and_log_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = JobStore::new(dir.path());
+
+        for (block_number, log_index) in [(10, BLOCK_STATE), (10, 2), (9, 5), (256, 0), (10, 0)] {
+            store
+                .put(&mut JobRecord::new(block_number, log_index, None, None))
+                .unwrap();
+        }
+        let mut finished = JobRecord::skipped(10, None);
+        store.put(&mut finished).unwrap();
+
+        let jobs: Vec<_> = store
+            .unfinished()
+            .unwrap()
+            .iter()
+            .map(|record| (record.block_number, record.log_index))
+            .collect();
+        assert_eq!(jobs, [(9, 5), (10, 0), (10, 2), (256, 0)]);
+        assert_eq!(
+            store.get(10, BLOCK_STATE).unwrap().unwrap().state,
+            JobState::Skipped
+        );
+        assert!(store.get(10, 1).unwrap().is_none());
+    }
+}
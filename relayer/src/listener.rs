@@ -1,124 +1,966 @@
-use std::time::Duration;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::Duration,
+};
 
 use alloy::{
     eips::{BlockId, BlockNumberOrTag},
-    primitives::Address,
+    primitives::{Address, B256, U256},
     providers::{DynProvider, Provider},
+    pubsub::Subscription,
+    rpc::types::{Filter, Log},
     sol,
+    sol_types::SolEvent,
 };
+use clap::ValueEnum;
 use eyre::Result;
 use futures_util::Stream;
 use tokio::time::sleep;
 
+use crate::{
+    checkpoint::Checkpoint,
+    error::{ListenerError, RelayerError},
+    provider::EthProvider,
+};
+
 sol!(
-    #[sol(rpc)]
+    #[sol(rpc, all_derives)]
     WorldIdentityManager,
     "abi/WorldIdentityManager.json"
 );
 
+/// Interval between two checks of the confirmed block
+const POLL_INTERVAL: Duration = Duration::from_secs(12);
+
+/// Messages returned by providers when an `eth_getLogs` range is too large or
+/// matches too many logs. Kept provider specific so that rate limit and quota
+/// errors, which often mention "exceeded" too, are not mistaken for them.
+const RANGE_ERRORS: [&str; 7] = [
+    // geth, Infura
+    "query returned more than",
+    // geth, Erigon
+    "exceed maximum block range",
+    // Alchemy
+    "log response size exceeded",
+    // Ankr
+    "block range is too wide",
+    // Nethermind, Reth, public endpoints
+    "range too large",
+    // QuickNode
+    "eth_getlogs is limited to",
+    // Cloudflare
+    "query timeout exceeded",
+];
+
+/// Which blocks the listener considers confirmed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ConfirmationPolicy {
+    /// Follow the `finalized` block tag. Reorgs cannot happen
+    Finalized,
+    /// Follow the `safe` block tag
+    Safe,
+    /// Follow the latest block minus a number of confirmations
+    Latest,
+}
+
+/// Kind of a tree change, mirrors `WorldIDIdentityManagerImplV1.TreeChange`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum TreeChangeKind {
+    Insertion,
+    Deletion,
+    Update,
+}
+
+impl TryFrom<u8> for TreeChangeKind {
+    type Error = eyre::Report;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(Self::Insertion),
+            1 => Ok(Self::Deletion),
+            2 => Ok(Self::Update),
+            _ => Err(eyre::eyre!("Unknown TreeChange kind {value}")),
+        }
+    }
+}
+
+/// A `TreeChanged` event that modified the World ID root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootChange {
+    pub kind: TreeChangeKind,
+    pub pre_root: U256,
+    pub post_root: U256,
+    pub block_number: u64,
+    /// Hash of the block holding the log, `None` if the provider omitted it
+    pub block_hash: Option<B256>,
+    pub tx_hash: B256,
+    pub log_index: u64,
+}
+
+/// Items produced by the listener stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenerEvent {
+    /// A root change included in a confirmed block
+    Changed(RootChange),
+    /// A root change previously emitted that was dropped by a chain reorg
+    Retracted(RootChange),
+    /// The root read from the contract state at a confirmed block differs
+    /// from the last known one, without a matching `TreeChanged` log
+    RootObserved { root: U256, block_number: u64 },
+    /// Every root change up to this finalized block was emitted
+    Finalized { block_number: u64 },
+}
+
+impl ListenerEvent {
+    /// Block the event refers to
+    pub fn block_number(&self) -> u64 {
+        match self {
+            Self::Changed(change) | Self::Retracted(change) => change.block_number,
+            Self::RootObserved { block_number, .. } | Self::Finalized { block_number } => {
+                *block_number
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct WorldIDListener {
-    provider: DynProvider,
+    provider: EthProvider,
     world_idm: Address,
+    policy: ConfirmationPolicy,
+    confirmations: u64,
+    /// Receive logs through `eth_subscribe`, requires a WebSocket provider
+    pubsub: bool,
+    /// Maximum number of blocks queried by a single `eth_getLogs` call
+    max_log_span: u64,
+    /// Storage slot of `latestRoot`, read at each confirmed block when set
+    state_slot: Option<U256>,
 }
 
 impl WorldIDListener {
-    pub fn new(provider: DynProvider, world_idm: Address) -> Self {
+    pub fn new(
+        provider: EthProvider,
+        world_idm: Address,
+        policy: ConfirmationPolicy,
+        confirmations: u64,
+        pubsub: bool,
+        max_log_span: u64,
+    ) -> Self {
         Self {
             provider,
             world_idm,
+            policy,
+            confirmations,
+            pubsub,
+            max_log_span: max_log_span.max(1),
+            state_slot: None,
         }
     }
 
-    /// Create a stream that periodically polls for new finalized events
+    /// Also detect root changes by reading `latestRoot()` and its storage
+    /// slot at every new confirmed block, in case logs were missed.
+    pub fn with_state_polling(mut self, latest_root_slot: U256) -> Self {
+        self.state_slot = Some(latest_root_slot);
+        self
+    }
+
+    /// Create a stream of new confirmed events.
+    ///
+    /// The stream starts right after `resume` if given, at the current
+    /// confirmed block otherwise. With a WebSocket provider logs are pushed
+    /// through `eth_subscribe` and released once their block is confirmed,
+    /// otherwise the listener periodically polls `eth_getLogs`.
     pub async fn subscribe(
         &self,
-    ) -> Result<impl Stream<Item = (WorldIdentityManager::TreeChanged, u64)>> {
-        // Get initial finalized block
-        let initial_finalized = get_finalized_block_number(&self.provider).await?;
-        tracing::info!("Starting relay from finalized block {initial_finalized}");
-
-        // Create contract instance once, outside the closure
-        let world_contract = WorldIdentityManager::new(self.world_idm, self.provider.clone());
-
-        // Create a manual polling stream
-        let stream = futures_util::stream::unfold(
-            (initial_finalized, self.provider.clone(), world_contract),
-            move |(mut last_block, provider, world_contract)| async move {
-                loop {
-                    // Sleep to avoid excessive polling
-                    sleep(Duration::from_secs(12)).await;
-
-                    // Get current finalized block
-                    let latest_finalized = match get_finalized_block_number(&provider).await {
-                        Ok(num) => num,
-                        Err(e) => {
-                            tracing::error!("Failed to get finalized block: {}", e);
-                            continue;
-                        }
-                    };
-
-                    // If we have new finalized blocks
-                    if latest_finalized > last_block {
-                        tracing::info!(
-                            "Checking for events from blocks {} to {}",
-                            last_block + 1,
-                            latest_finalized
-                        );
-
-                        // Query for new events in the finalized range
-                        let new_events = match world_contract
-                            .TreeChanged_filter()
-                            .from_block(BlockNumberOrTag::Number(last_block + 1))
-                            .to_block(BlockNumberOrTag::Number(latest_finalized))
-                            .query()
-                            .await
-                        {
-                            Ok(events) => events,
-                            Err(e) => {
-                                tracing::error!("Failed to query events: {}", e);
-                                continue;
-                            }
-                        };
-
-                        // Update state
-                        last_block = latest_finalized;
-
-                        // Process each new event
-                        for (event, log) in new_events {
-                            // Event results from query() are already typed correctly
-                            tracing::info!("New TreeChanged event");
-                            let block_number = log.block_number.unwrap();
-
-                            // Skip events where root hasn't changed
-                            if event.preRoot == event.postRoot {
-                                tracing::info!("latesRoot has not changed, ignoring...");
-                                continue;
-                            }
-
-                            return Some((
-                                (event, block_number),
-                                (last_block, provider, world_contract),
-                            ));
-                        }
+        resume: Option<Checkpoint>,
+    ) -> Result<impl Stream<Item = ListenerEvent>> {
+        let (initial_block, initial_hash) = match resume {
+            Some(checkpoint) => {
+                // The checkpoint block may hold logs that were not processed yet
+                let block = checkpoint.block_number.saturating_sub(1);
+                tracing::info!(
+                    "Resuming relay after block {} log {}",
+                    checkpoint.block_number,
+                    checkpoint.log_index
+                );
+                (block, get_block_hash(&self.provider, block).await?)
+            }
+            None => {
+                // Get initial confirmed block
+                let (block, hash) =
+                    get_confirmed_block(&self.provider, self.policy, self.confirmations).await?;
+                tracing::info!("Starting relay from {:?} block {block}", self.policy);
+                (block, hash)
+            }
+        };
+
+        // Finalized blocks cannot be reorged, only track hashes otherwise
+        let reorg = match self.policy {
+            ConfirmationPolicy::Finalized => None,
+            _ => {
+                let mut detector = ReorgDetector::default();
+                detector.track(initial_block, initial_hash);
+                Some(detector)
+            }
+        };
+
+        // Baseline for the state polling detector
+        let last_root = match self.state_slot {
+            Some(slot) => Some(self.read_latest_root(initial_block, slot).await?),
+            None => None,
+        };
+
+        let state = PollState {
+            listener: self.clone(),
+            last_block: initial_block,
+            pending: VecDeque::new(),
+            reorg,
+            subscription: None,
+            unconfirmed: BTreeMap::new(),
+            backfill: true,
+            span: LogSpan::new(self.max_log_span),
+            resume,
+            last_root,
+        };
+
+        // Create a manual polling stream. Events of a polled range are buffered
+        // and yielded one by one before querying the next range.
+        let stream = futures_util::stream::unfold(state, |mut state| async move {
+            let item = state.next().await;
+            Some((item, state))
+        });
+
+        Ok(stream)
+    }
+
+    /// Fetch the root changes emitted in an explicit inclusive block range
+    pub async fn root_changes(&self, from: u64, to: u64) -> Result<Vec<RootChange>> {
+        let mut span = LogSpan::new(self.max_log_span);
+        self.query_root_changes(from, to, &mut span).await
+    }
+
+    /// Query the root changes emitted in the inclusive block range, splitting
+    /// it into chunks of at most `span` blocks.
+    async fn query_root_changes(
+        &self,
+        from: u64,
+        to: u64,
+        span: &mut LogSpan,
+    ) -> Result<Vec<RootChange>> {
+        let mut changes = Vec::new();
+        let mut start = from;
+
+        while start <= to {
+            let end = to.min(start.saturating_add(span.current - 1));
+            match self.query_chunk(start, end).await {
+                Ok(chunk) => {
+                    changes.extend(chunk);
+                    start = end + 1;
+                    span.grow();
+                }
+                Err(e) if is_range_error(&e) && span.shrink() => {
+                    tracing::warn!(
+                        "Log range {start}-{end} rejected, retrying with {} blocks: {}",
+                        span.current,
+                        e
+                    );
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Query the root changes of a single `eth_getLogs` call
+    async fn query_chunk(&self, from: u64, to: u64) -> Result<Vec<RootChange>> {
+        let world_idm = self.world_idm;
+        self.provider
+            .consensus(move |provider| async move {
+                let world_contract = WorldIdentityManager::new(world_idm, provider);
+                let mut events = world_contract
+                    .TreeChanged_filter()
+                    .from_block(BlockNumberOrTag::Number(from))
+                    .to_block(BlockNumberOrTag::Number(to))
+                    .query()
+                    .await?;
+
+                // Providers usually return logs ordered, but do not rely on it
+                events.sort_by_key(|(_, log)| (log.block_number, log.log_index));
+
+                // Event results from query() are already typed correctly
+                let changes: Vec<RootChange> = events
+                    .iter()
+                    .filter_map(|(event, log)| root_change(event, log).transpose())
+                    .collect::<Result<_, _>>()
+                    .map_err(RelayerError::from)?;
+                Ok(changes)
+            })
+            .await
+    }
+
+    /// Observe the root held at the newest finalized block
+    pub async fn observe_root(&self) -> Result<ListenerEvent> {
+        let block_number = get_finalized_block_number(&self.provider).await?;
+        let root = self.latest_root(block_number).await?;
+
+        Ok(ListenerEvent::RootObserved { root, block_number })
+    }
+
+    /// Read `latestRoot()` at a block
+    pub async fn latest_root(&self, block_number: u64) -> Result<U256> {
+        let world_idm = self.world_idm;
+        let block_id = BlockId::from(block_number);
+
+        self.provider
+            .consensus(move |provider| async move {
+                let world_contract = WorldIdentityManager::new(world_idm, provider);
+                Ok(world_contract.latestRoot().block(block_id).call().await?._0)
+            })
+            .await
+    }
+
+    /// Read the World ID root at a block through both `latestRoot()` and the
+    /// raw storage slot, failing if they disagree.
+    async fn read_latest_root(&self, block_number: u64, slot: U256) -> Result<U256> {
+        let world_idm = self.world_idm;
+        let block_id = BlockId::from(block_number);
+
+        let (root, raw) = self
+            .provider
+            .consensus(move |provider| async move {
+                let raw = provider
+                    .get_storage_at(world_idm, slot)
+                    .block_id(block_id)
+                    .await?;
+                let world_contract = WorldIdentityManager::new(world_idm, provider);
+                let root = world_contract.latestRoot().block(block_id).call().await?._0;
+                Ok((root, raw))
+            })
+            .await?;
+
+        if root != raw {
+            return Err(RelayerError::from(ListenerError::SlotMismatch {
+                block_number,
+                slot,
+                root,
+                raw,
+            })
+            .into());
+        }
+
+        Ok(root)
+    }
+
+    /// Subscribe to the `TreeChanged` logs of the World Identity Manager
+    async fn subscribe_logs(&self) -> Result<Subscription<Log>> {
+        let filter = Filter::new()
+            .address(self.world_idm)
+            .event_signature(WorldIdentityManager::TreeChanged::SIGNATURE_HASH);
+
+        self.provider
+            .failover(|provider| {
+                let filter = filter.clone();
+                async move { Ok(provider.subscribe_logs(&filter).await?) }
+            })
+            .await
+    }
+}
+
+/// Whether a later change of the same block replaced the root of `change`.
+/// The state of a block only holds the root of its last change, earlier ones
+/// cannot be proven at that block.
+pub fn is_overridden(change: &RootChange, changes: &[RootChange]) -> bool {
+    changes.iter().any(|other| {
+        other.block_number == change.block_number && other.log_index > change.log_index
+    })
+}
+
+/// Build the root change of a `TreeChanged` log, `None` if the root is unchanged
+fn root_change(
+    event: &WorldIdentityManager::TreeChanged,
+    log: &Log,
+) -> Result<Option<RootChange>, ListenerError> {
+    tracing::info!("New TreeChanged event");
+
+    // Skip events where root hasn't changed
+    if event.preRoot == event.postRoot {
+        tracing::info!("latesRoot has not changed, ignoring...");
+        return Ok(None);
+    }
+
+    let kind = match TreeChangeKind::try_from(event.kind) {
+        Ok(kind) => kind,
+        Err(e) => {
+            tracing::warn!("{}, ignoring...", e);
+            return Ok(None);
+        }
+    };
+    let block_number = log.block_number.ok_or(ListenerError::MissingBlockNumber {
+        tx_hash: log.transaction_hash,
+    })?;
+
+    Ok(Some(RootChange {
+        kind,
+        pre_root: event.preRoot,
+        post_root: event.postRoot,
+        block_number,
+        block_hash: log.block_hash,
+        tx_hash: log.transaction_hash.unwrap_or_default(),
+        log_index: log.log_index.unwrap_or_default(),
+    }))
+}
+
+/// State carried across iterations of the polling stream
+struct PollState {
+    listener: WorldIDListener,
+    /// Last block whose events have been fully buffered
+    last_block: u64,
+    /// Events waiting to be yielded
+    pending: VecDeque<ListenerEvent>,
+    /// Reorg tracking, disabled when following finalized blocks
+    reorg: Option<ReorgDetector>,
+    /// Live log subscription when running in pubsub mode
+    subscription: Option<Subscription<Log>>,
+    /// Pushed root changes waiting for their block to be confirmed
+    unconfirmed: BTreeMap<(u64, u64), RootChange>,
+    /// Whether pushed logs may be missing and the next range must be queried
+    backfill: bool,
+    /// Current `eth_getLogs` block span
+    span: LogSpan,
+    /// Logs at or before this checkpoint were already processed
+    resume: Option<Checkpoint>,
+    /// Root confirmed at `last_block`, only tracked for state polling
+    last_root: Option<U256>,
+}
+
+impl PollState {
+    /// Wait for the next listener event
+    async fn next(&mut self) -> ListenerEvent {
+        loop {
+            // Drain buffered events before polling again
+            if let Some(item) = self.pending.pop_front() {
+                return item;
+            }
+
+            if self.listener.pubsub {
+                self.receive_logs().await;
+            } else {
+                // Sleep to avoid excessive polling
+                sleep(POLL_INTERVAL).await;
+            }
+
+            if let Err(e) = self.poll().await {
+                tracing::error!("Failed to poll for events: {}", e);
+            }
+        }
+    }
+
+    /// Buffer pushed logs until the next confirmation check is due,
+    /// resubscribing whenever the subscription drops.
+    async fn receive_logs(&mut self) {
+        let tick = sleep(POLL_INTERVAL);
+        tokio::pin!(tick);
+
+        loop {
+            if self.subscription.is_none() {
+                match self.listener.subscribe_logs().await {
+                    Ok(subscription) => {
+                        tracing::info!("Subscribed to TreeChanged logs");
+                        // Logs emitted while disconnected are back-filled
+                        self.backfill = true;
+                        self.subscription = Some(subscription);
+                    }
+                    Err(e) => {
+                        tracing::error!("Failed to subscribe to logs: {}", e);
+                        (&mut tick).await;
+                        return;
                     }
                 }
-            },
-        );
+            }
+            let Some(subscription) = self.subscription.as_mut() else {
+                return;
+            };
 
-        Ok(stream)
+            let log = tokio::select! {
+                _ = &mut tick => return,
+                log = subscription.recv() => log,
+            };
+
+            match log {
+                Ok(log) => self.on_log(log),
+                Err(e) => {
+                    tracing::warn!("Log subscription dropped: {}, resubscribing", e);
+                    self.subscription = None;
+                }
+            }
+        }
+    }
+
+    /// Buffer a pushed log, or drop it if a reorg removed it
+    fn on_log(&mut self, log: Log) {
+        let event = match WorldIdentityManager::TreeChanged::decode_log_data(log.data(), true) {
+            Ok(event) => event,
+            Err(e) => {
+                tracing::warn!("Failed to decode TreeChanged log: {}", e);
+                return;
+            }
+        };
+        let change = match root_change(&event, &log) {
+            Ok(Some(change)) => change,
+            Ok(None) => return,
+            // Back-filled once its block is confirmed
+            Err(e) => {
+                tracing::warn!("Ignoring pushed log: {}", e);
+                self.backfill = true;
+                return;
+            }
+        };
+
+        let key = (change.block_number, change.log_index);
+        if !log.removed {
+            self.unconfirmed.insert(key, change);
+        } else if self.unconfirmed.remove(&key).is_some() {
+            tracing::info!(
+                "Unconfirmed root {:?} at block {} removed by a reorg",
+                change.post_root,
+                change.block_number
+            );
+        } else {
+            // Already confirmed, the reorg detector will retract it
+            tracing::warn!(
+                "Removed log for confirmed root {:?} at block {}",
+                change.post_root,
+                change.block_number
+            );
+        }
     }
+
+    /// Check for reorgs and buffer the events of newly confirmed blocks
+    async fn poll(&mut self) -> Result<()> {
+        let provider = &self.listener.provider;
+
+        if let Some(reorg) = self.reorg.as_mut() {
+            if let Some(ancestor) = reorg.check(provider).await? {
+                tracing::warn!(
+                    "Chain reorg detected, rewinding from block {} to {}",
+                    self.last_block,
+                    ancestor
+                );
+                let retracted = reorg.rewind(ancestor);
+                // The root at the ancestor is the one the oldest retracted
+                // change replaced
+                if let (Some(root), Some(oldest)) = (self.last_root.as_mut(), retracted.last()) {
+                    *root = oldest.pre_root;
+                }
+                self.pending
+                    .extend(retracted.into_iter().map(ListenerEvent::Retracted));
+                self.last_block = self.last_block.min(ancestor);
+                // Pushed logs of the rewound range were already consumed
+                self.backfill = true;
+            }
+        }
+
+        // Get current confirmed block
+        let (latest, latest_hash) =
+            get_confirmed_block(provider, self.listener.policy, self.listener.confirmations)
+                .await?;
+
+        // Nothing to do until we have new confirmed blocks
+        if latest <= self.last_block {
+            return Ok(());
+        }
+
+        // Pushed logs are only consumed once the whole poll went through, a
+        // failure below leaves them for the next one
+        let query = self.subscription.is_none() || self.backfill;
+        let changes: Vec<RootChange> = if query {
+            tracing::info!(
+                "Checking for events from blocks {} to {}",
+                self.last_block + 1,
+                latest
+            );
+
+            // Query for new events in the confirmed range, superseding any pushed log
+            self.listener
+                .query_root_changes(self.last_block + 1, latest, &mut self.span)
+                .await?
+        } else {
+            self.unconfirmed
+                .range(..(latest + 1, 0))
+                .map(|(_, change)| change)
+                .filter(|change| change.block_number > self.last_block)
+                .cloned()
+                .collect()
+        };
+
+        // Every confirmed change counts, including the ones already processed
+        let last_root = self
+            .last_root
+            .map(|root| changes.last().map_or(root, |change| change.post_root));
+
+        // Drop logs processed before a restart
+        let changes: Vec<_> = match self.resume {
+            Some(checkpoint) => changes
+                .into_iter()
+                .filter(|change| checkpoint.precedes(change))
+                .collect(),
+            None => changes,
+        };
+
+        // Resolve everything else that may fail before updating the state
+        let mut hashes = Vec::new();
+        let mut finalized = latest;
+        if self.reorg.is_some() {
+            for change in &changes {
+                // A missing hash is unknown, not a different fork
+                hashes.push(match change.block_hash {
+                    Some(hash) => hash,
+                    None => get_block_hash(provider, change.block_number).await?,
+                });
+            }
+            // The finalized head runs ahead of `latest` when the confirmations
+            // exceed the finality lag, the blocks in between were not queried
+            finalized = get_finalized_block_number(provider).await?.min(latest);
+        }
+        let observed = match self.listener.state_slot {
+            Some(slot) => Some(self.listener.read_latest_root(latest, slot).await?),
+            None => None,
+        };
+
+        // Pushed logs up to the confirmed block are consumed, the rest stays
+        // buffered
+        self.unconfirmed = self.unconfirmed.split_off(&(latest + 1, 0));
+        if query {
+            self.backfill = self.subscription.is_none();
+        }
+
+        if let Some(reorg) = self.reorg.as_mut() {
+            reorg.track(latest, latest_hash);
+            for (change, hash) in changes.iter().zip(hashes) {
+                reorg.record(change.clone(), hash);
+            }
+            // Anything at or below the finalized block can no longer be reorged
+            reorg.prune(finalized);
+        }
+
+        // The whole range is buffered, advance the cursor
+        self.pending
+            .extend(changes.into_iter().map(ListenerEvent::Changed));
+        self.last_block = latest;
+        self.last_root = last_root;
+
+        if let Some(root) = observed {
+            if self.last_root != Some(root) {
+                tracing::warn!("Root {root:#x} at block {latest} not seen in TreeChanged logs");
+                self.pending.push_back(ListenerEvent::RootObserved {
+                    root,
+                    block_number: latest,
+                });
+                self.last_root = Some(root);
+            }
+        }
+
+        // Lets the checkpoint move forward through blocks without root changes
+        self.pending.push_back(ListenerEvent::Finalized {
+            block_number: finalized,
+        });
+
+        Ok(())
+    }
+}
+
+/// Block span of `eth_getLogs` requests. Halves when the provider rejects a
+/// range and grows back on success, up to the configured maximum.
+#[derive(Debug, Clone, Copy)]
+struct LogSpan {
+    current: u64,
+    max: u64,
+}
+
+impl LogSpan {
+    fn new(max: u64) -> Self {
+        Self { current: max, max }
+    }
+
+    /// Halve the span, returns `false` if it cannot shrink any further
+    fn shrink(&mut self) -> bool {
+        if self.current == 1 {
+            return false;
+        }
+        self.current /= 2;
+        true
+    }
+
+    fn grow(&mut self) {
+        self.current = self.current.saturating_mul(2).min(self.max);
+    }
+}
+
+/// Whether the error is a provider rejecting the size of a log query
+fn is_range_error(error: &eyre::Report) -> bool {
+    error.chain().any(|cause| {
+        let message = cause.to_string().to_lowercase();
+        RANGE_ERRORS
+            .iter()
+            .any(|fragment| message.contains(fragment))
+    })
+}
+
+/// Tracks the hashes of processed, not yet finalized, blocks to detect when
+/// one of them has been replaced by a reorg.
+#[derive(Debug, Default)]
+struct ReorgDetector {
+    /// Hashes of the range ends and of the blocks holding emitted events
+    hashes: BTreeMap<u64, B256>,
+    /// Root changes emitted from blocks that are not yet finalized
+    emitted: Vec<RootChange>,
+}
+
+impl ReorgDetector {
+    fn track(&mut self, number: u64, hash: B256) {
+        self.hashes.insert(number, hash);
+    }
+
+    fn record(&mut self, change: RootChange, hash: B256) {
+        self.track(change.block_number, hash);
+        self.emitted.push(change);
+    }
+
+    /// Forget everything at or below the finalized block, keeping the newest
+    /// finalized entry as an anchor for rewinds.
+    fn prune(&mut self, finalized: u64) {
+        let anchor = self
+            .hashes
+            .range(..=finalized)
+            .next_back()
+            .map(|(number, hash)| (*number, *hash));
+        self.hashes.retain(|number, _| *number > finalized);
+        if let Some((number, hash)) = anchor {
+            self.hashes.insert(number, hash);
+        }
+        self.emitted
+            .retain(|change| change.block_number > finalized);
+    }
+
+    /// Return the newest tracked block still canonical if a reorg replaced
+    /// any tracked block, `None` otherwise.
+    async fn check(&self, provider: &EthProvider) -> Result<Option<u64>> {
+        // The newest range end covers its whole ancestry, event blocks are
+        // checked as well in case their logs came from a stale fork.
+        let mut candidates: Vec<u64> = self.emitted.iter().map(|c| c.block_number).collect();
+        candidates.extend(self.hashes.keys().next_back());
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        let mut replaced = None;
+        for number in candidates {
+            if get_block_hash(provider, number).await? != self.hashes[&number] {
+                replaced = Some(number);
+                break;
+            }
+        }
+
+        let Some(replaced) = replaced else {
+            return Ok(None);
+        };
+
+        // Walk back to the newest tracked block that is still canonical
+        for (number, hash) in self.hashes.range(..replaced).rev() {
+            if get_block_hash(provider, *number).await? == *hash {
+                return Ok(Some(*number));
+            }
+        }
+
+        Err(RelayerError::from(ListenerError::DeepReorg(replaced)).into())
+    }
+
+    /// Drop every tracked block above the ancestor and return the root
+    /// changes that must be retracted, newest first.
+    fn rewind(&mut self, ancestor: u64) -> Vec<RootChange> {
+        self.hashes.retain(|number, _| *number <= ancestor);
+
+        let (kept, mut retracted): (Vec<_>, Vec<_>) = self
+            .emitted
+            .drain(..)
+            .partition(|change| change.block_number <= ancestor);
+        self.emitted = kept;
+        retracted.reverse();
+
+        retracted
+    }
+}
+
+/// Helper to get the number and hash of the newest block matching the policy
+async fn get_confirmed_block(
+    provider: &EthProvider,
+    policy: ConfirmationPolicy,
+    confirmations: u64,
+) -> Result<(u64, B256)> {
+    let tag = match policy {
+        ConfirmationPolicy::Finalized => BlockNumberOrTag::Finalized,
+        ConfirmationPolicy::Safe => BlockNumberOrTag::Safe,
+        ConfirmationPolicy::Latest => {
+            // Endpoints rarely agree on the tip, only the confirmed block is
+            // checked for quorum
+            let latest = provider
+                .failover(|provider| async move { Ok(provider.get_block_number().await?) })
+                .await?;
+            BlockNumberOrTag::Number(latest.saturating_sub(confirmations))
+        }
+    };
+
+    provider
+        .consensus(move |provider| async move { get_block_id(&provider, tag).await })
+        .await
 }
 
 /// Helper to get the latest finalized block number
-async fn get_finalized_block_number(provider: &DynProvider) -> Result<u64> {
-    let finalized_block = provider
-        .get_block(
-            BlockId::from(BlockNumberOrTag::Finalized),
-            Default::default(),
-        )
+async fn get_finalized_block_number(provider: &EthProvider) -> Result<u64> {
+    let (number, _) = get_confirmed_block(provider, ConfirmationPolicy::Finalized, 0).await?;
+
+    Ok(number)
+}
+
+/// Helper to get the canonical hash of a block
+async fn get_block_hash(provider: &EthProvider, number: u64) -> Result<B256> {
+    let (_, hash) = provider
+        .consensus(move |provider| async move {
+            get_block_id(&provider, BlockNumberOrTag::Number(number)).await
+        })
+        .await?;
+
+    Ok(hash)
+}
+
+/// Helper to get the number and hash of a block from a single endpoint
+async fn get_block_id(provider: &DynProvider, tag: BlockNumberOrTag) -> Result<(u64, B256)> {
+    let block = provider
+        .get_block(BlockId::from(tag), Default::default())
         .await?
-        .ok_or_else(|| eyre::eyre!("Failed to get finalized block"))?;
+        .ok_or_else(|| eyre::eyre!("Failed to get {tag} block"))?;
+
+    Ok((block.header.number, block.header.hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(block_number: u64, log_index: u64) -> RootChange {
+        RootChange {
+            kind: TreeChangeKind::Insertion,
+            pre_root: U256::from(block_number),
+            post_root: U256::from(block_number + 1),
+            block_number,
+            block_hash: None,
+            tx_hash: B256::ZERO,
+            log_index,
+        }
+    }
+
+    fn hash(number: u64) -> B256 {
+        B256::from(U256::from(number))
+    }
+
+    #[test]
+    fn reorg_detector_rewinds_changes_above_ancestor() {
+        let mut detector = ReorgDetector::default();
+        detector.track(10, hash(10));
+        detector.record(change(11, 0), hash(11));
+        detector.record(change(12, 0), hash(12));
+        detector.record(change(12, 1), hash(12));
+
+        let retracted = detector.rewind(11);
 
-    Ok(finalized_block.header.number)
+        assert_eq!(retracted, vec![change(12, 1), change(12, 0)]);
+        assert_eq!(detector.emitted, vec![change(11, 0)]);
+        assert_eq!(
+            detector.hashes.keys().copied().collect::<Vec<_>>(),
+            [10, 11]
+        );
+    }
+
+    #[test]
+    fn reorg_detector_prune_keeps_finalized_anchor() {
+        let mut detector = ReorgDetector::default();
+        detector.track(10, hash(10));
+        detector.record(change(11, 0), hash(11));
+        detector.track(13, hash(13));
+        detector.record(change(14, 0), hash(14));
+
+        detector.prune(12);
+
+        assert_eq!(
+            detector.hashes.keys().copied().collect::<Vec<_>>(),
+            [11, 13, 14]
+        );
+        assert_eq!(detector.hashes[&11], hash(11));
+        assert_eq!(detector.emitted, vec![change(14, 0)]);
+    }
+
+    #[test]
+    fn reorg_detector_tracks_resolved_hash() {
+        let mut detector = ReorgDetector::default();
+        detector.record(change(11, 0), hash(11));
+
+        assert_eq!(detector.hashes[&11], hash(11));
+    }
+
+    #[test]
+    fn only_last_change_of_block_is_not_overridden() {
+        let changes = [change(10, 1), change(10, 4), change(11, 0)];
+
+        assert!(is_overridden(&changes[0], &changes));
+        assert!(!is_overridden(&changes[1], &changes));
+        assert!(!is_overridden(&changes[2], &changes));
+    }
+
+    #[test]
+    fn log_span_shrinks_to_one_and_grows_to_max() {
+        let mut span = LogSpan::new(5);
+
+        assert!(span.shrink());
+        assert_eq!(span.current, 2);
+        assert!(span.shrink());
+        assert_eq!(span.current, 1);
+        assert!(!span.shrink());
+        assert_eq!(span.current, 1);
+
+        span.grow();
+        assert_eq!(span.current, 2);
+        span.grow();
+        span.grow();
+        assert_eq!(span.current, 5);
+    }
+
+    #[test]
+    fn range_errors_are_detected() {
+        for message in [
+            "query returned more than 10000 results",
+            "exceed maximum block range: 5000",
+            "Log response size exceeded. You can make eth_getLogs requests with up to a 2K block range",
+            "block range is too wide",
+            "block range too large",
+            "eth_getLogs is limited to a 10,000 range",
+            "query timeout exceeded",
+        ] {
+            let error = eyre::eyre!("{message}").wrap_err("Failed to query logs");
+            assert!(is_range_error(&error), "{message}");
+        }
+    }
+
+    #[test]
+    fn rate_limit_errors_are_not_range_errors() {
+        for message in [
+            "rate limit exceeded",
+            "daily request count exceeded, request rate limited",
+            "Your app has exceeded its compute units per second capacity",
+            "more than 25 requests per second",
+            "project ID request rate exceeded",
+            "429 Too Many Requests",
+        ] {
+            assert!(!is_range_error(&eyre::eyre!("{message}")), "{message}");
+        }
+    }
 }
@@ -3,33 +3,55 @@
 //! A zk-SNARK based relayer that monitors World ID identity changes and generates
 //! storage inclusion proofs for state transitions.
 
+mod artifacts;
+mod backfill;
+mod checkpoint;
+mod error;
+mod jobs;
 mod listener;
 mod prover;
+mod provider;
 mod publisher;
+mod queue;
 mod relayer;
+mod retry;
+mod util;
 
-use clap::Parser;
+use std::{path::PathBuf, time::Duration};
+
+use clap::{Parser, Subcommand};
 use eyre::Result;
 use tracing_subscriber::{fmt, EnvFilter};
 use alloy_chains::Chain;
 
-use relayer::RelayerBuilder;
+use listener::{ConfirmationPolicy, TreeChangeKind};
+use prover::{MockProver, Prover, ProverBackend, Risc0Prover};
+use queue::CoalescePolicy;
+use relayer::{RelayExit, RelayerBuilder};
 
 #[derive(Debug, Parser)]
 #[command(version, about, author)]
 struct Config {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Chain to operate on (e.g., "sepolia", "mainnet")
     #[arg(short = 'c', long, env = "CHAIN", default_value = "sepolia")]
     chain: Chain,
 
-    /// Ethereum JSON-RPC endpoint URL
+    /// Ethereum JSON-RPC endpoint URLs, comma separated, in failover order
     #[arg(
         short = 'e',
         long,
         env = "ETH_RPC_URL",
+        value_delimiter = ',',
         default_value = "https://eth.llamarpc.com"
     )]
-    ethereum_rpc_url: String,
+    ethereum_rpc_url: Vec<String>,
+
+    /// Require a majority of Ethereum endpoints to agree on blocks and logs
+    #[arg(long, env = "ETH_RPC_QUORUM")]
+    rpc_quorum: bool,
 
     /// Starknet JSON-RPC endpoint URL
     #[arg(
@@ -52,6 +74,10 @@ struct Config {
     #[arg(short = 'v', long, env = "RELAYER_VERIFIER", required = true)]
     relayer_verifier: String,
 
+    /// JSON file listing additional Starknet destinations to publish to
+    #[arg(long, env = "DESTINATIONS_FILE")]
+    destinations_file: Option<PathBuf>,
+
     /// Address of the WorldIdentityManager contract
     #[arg(short = 'm', long, env = "WORLD_IDENTITY_MANAGER", required = true)]
     world_id_manager: String,
@@ -59,17 +85,273 @@ struct Config {
     /// Storage slot of the latestRoot variable in WorldIdentityManager contract
     #[arg(short = 'r', long, env = "WORLD_ID_LATEST_ROOT_SLOT", default_value = "302")]
     world_id_latest_root_slot: u64,
+
+    /// Blocks considered confirmed by the listener (finalized, safe or latest)
+    #[arg(
+        long,
+        env = "CONFIRMATION_POLICY",
+        value_enum,
+        default_value_t = ConfirmationPolicy::Finalized
+    )]
+    confirmation_policy: ConfirmationPolicy,
+
+    /// Confirmations required on top of the latest block with the `latest` policy
+    #[arg(long, env = "CONFIRMATIONS", default_value = "12")]
+    confirmations: u64,
+
+    /// Maximum number of blocks queried by a single eth_getLogs call
+    #[arg(long, env = "MAX_LOG_SPAN", default_value = "10000")]
+    max_log_span: u64,
+
+    /// File storing the last processed block and log index
+    #[arg(long, env = "STATE_FILE", default_value = "relayer-state.json")]
+    state_file: PathBuf,
+
+    /// Block to start relaying from, overriding the stored checkpoint
+    #[arg(long, env = "START_BLOCK")]
+    start_block: Option<u64>,
+
+    /// Tree change kinds to relay (insertion, deletion, update)
+    #[arg(
+        long,
+        env = "RELAY_KINDS",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "insertion,deletion,update"
+    )]
+    relay_kinds: Vec<TreeChangeKind>,
+
+    /// Also detect root changes by reading latestRoot at every confirmed block
+    #[arg(long, env = "STATE_POLLING")]
+    state_polling: bool,
+
+    /// Prove only the newest waiting root, or every root in order
+    #[arg(
+        long,
+        env = "COALESCE_POLICY",
+        value_enum,
+        default_value_t = CoalescePolicy::Newest
+    )]
+    coalesce: CoalescePolicy,
+
+    /// Seconds given to in-flight proving and publishing on shutdown
+    #[arg(long, env = "SHUTDOWN_GRACE_SECS", default_value = "600")]
+    shutdown_grace: u64,
+
+    /// Attempts of each fetch, prove or publish step before dead-lettering a root
+    #[arg(long, env = "MAX_ATTEMPTS", default_value = "5")]
+    max_attempts: u32,
+
+    /// Seconds before the first retry, doubled on every attempt
+    #[arg(long, env = "RETRY_BASE_DELAY_SECS", default_value = "5")]
+    retry_base_delay: u64,
+
+    /// Upper bound in seconds of the delay between two retries
+    #[arg(long, env = "RETRY_MAX_DELAY_SECS", default_value = "300")]
+    retry_max_delay: u64,
+
+    /// File listing the roots that could not be relayed
+    #[arg(
+        long,
+        env = "DEAD_LETTER_FILE",
+        default_value = "relayer-dead-letters.json"
+    )]
+    dead_letter_file: PathBuf,
+
+    /// Relay the current root again when no root was relayed for this many
+    /// seconds, keeping the Starknet store fresh
+    #[arg(long, env = "HEARTBEAT_INTERVAL_SECS")]
+    heartbeat_interval: Option<u64>,
+
+    /// Proving backend
+    #[arg(long, env = "PROVER", value_enum, default_value = "local")]
+    prover: ProverBackend,
+
+    /// Seconds a proof may take before the job is cancelled and retried
+    #[arg(long, env = "PROVING_TIMEOUT_SECS", default_value = "3600")]
+    proving_timeout: u64,
+
+    /// Do not execute the guest before proving it locally
+    #[arg(long, env = "SKIP_PREFLIGHT")]
+    skip_preflight: bool,
+
+    /// Directory where proofs are saved and reused from on retry or restart
+    #[arg(long, env = "ARTIFACT_DIR", default_value = "relayer-artifacts")]
+    artifact_dir: PathBuf,
+
+    /// Directory of the database keeping the relay jobs and their progress
+    #[arg(long, env = "JOB_STORE", default_value = "relayer-jobs")]
+    job_store: PathBuf,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Relay new root changes as they are detected (default)
+    Relay,
+    /// Re-process the root changes of a past block range
+    Backfill {
+        /// First block of the range (inclusive)
+        #[arg(long)]
+        from_block: u64,
+
+        /// Last block of the range (inclusive)
+        #[arg(long)]
+        to_block: u64,
+
+        /// Generate a proof for every root change
+        #[arg(long)]
+        prove: bool,
+
+        /// Publish the generated proofs to Starknet
+        #[arg(long, requires = "prove")]
+        publish: bool,
+    },
+    /// Execute the guest for a block without proving it and print the cycle
+    /// report
+    Execute {
+        /// Block whose World ID root is executed
+        #[arg(long)]
+        block: u64,
+    },
+    /// Inspect or replay the roots that could not be relayed
+    DeadLetters {
+        #[command(subcommand)]
+        action: DeadLetterAction,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum DeadLetterAction {
+    /// List the dead-lettered roots
+    List,
+    /// Relay the dead-lettered roots again
+    Replay {
+        /// Only replay the roots of this block
+        #[arg(long)]
+        block: Option<u64>,
+    },
+}
+
+fn main() -> Result<()> {
     dotenvy::dotenv()?;
     fmt().with_env_filter(EnvFilter::from_default_env()).init();
+
+    // Local proofs run in a child process of the relayer
+    let mut args = std::env::args_os().skip(1);
+    if args.next().is_some_and(|arg| arg == prover::LOCAL_JOB_ARG) {
+        return prover::run_local_job(args);
+    }
+
     tracing::info!("Starting relayer");
 
-    let config = Config::parse();
+    let mut config = Config::parse();
     tracing::debug!(?config, "Loaded configuration");
+    let command = config.command.take().unwrap_or(Command::Relay);
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    let result = runtime.block_on(async {
+        match config.prover {
+            ProverBackend::Local => run(config, command, Risc0Prover::local()).await,
+            ProverBackend::Bonsai => run(config, command, Risc0Prover::bonsai()).await,
+            ProverBackend::Dev => run(config, command, Risc0Prover::dev()).await,
+            ProverBackend::Mock => run(config, command, MockProver).await,
+        }
+    });
+
+    // Dropping the runtime would wait for proving jobs still running on
+    // blocking threads past the shutdown grace period
+    runtime.shutdown_timeout(Duration::ZERO);
+
+    result
+}
+
+/// Run the command with the selected prover backend
+async fn run<P: Prover>(config: Config, command: Command, prover: P) -> Result<()> {
+    let relayer = RelayerBuilder::new(config).build(prover).await?;
+    match command {
+        Command::Relay => match relayer.relay(shutdown_signal()).await? {
+            RelayExit::Completed => {
+                tracing::info!("Listener stream ended, relayer stopped");
+                Ok(())
+            }
+            RelayExit::Drained => {
+                tracing::info!("In-flight work completed, relayer stopped");
+                Ok(())
+            }
+            RelayExit::Interrupted => Err(eyre::eyre!(
+                "Shutdown grace period elapsed, in-flight work was aborted"
+            )),
+        },
+        Command::Backfill {
+            from_block,
+            to_block,
+            prove,
+            publish,
+        } => {
+            if from_block > to_block {
+                return Err(eyre::eyre!(
+                    "Invalid range: --from-block {from_block} is after --to-block {to_block}"
+                ));
+            }
+
+            let report = relayer
+                .backfill(from_block, to_block, prove, publish)
+                .await?;
+            println!("{report}");
+            Ok(())
+        }
+        Command::Execute { block } => {
+            let report = relayer.execute(block).await?;
+            println!("{report}");
+            Ok(())
+        }
+        Command::DeadLetters { action } => match action {
+            DeadLetterAction::List => {
+                let letters = relayer.dead_letters()?;
+                println!("{} dead letters", letters.len());
+                for letter in letters {
+                    println!("  {letter}");
+                }
+                Ok(())
+            }
+            DeadLetterAction::Replay { block } => {
+                let (relayed, failed) = relayer.replay(block).await?;
+                println!("Replayed {relayed} dead letters, {failed} still failing");
+                Ok(())
+            }
+        },
+    }
+}
+
+/// Resolve once SIGINT or SIGTERM is received
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for SIGINT: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                tracing::error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
 
-    let relayer = RelayerBuilder::new(config).build().await?;
-    relayer.relay().await
+    tokio::select! {
+        _ = interrupt => {},
+        _ = terminate => {},
+    }
+    tracing::info!("Shutdown signal received");
 }
@@ -1,3 +1,26 @@
+use std::{
+    env,
+    ffi::OsString,
+    fmt, fs,
+    future::Future,
+    io::{self, Write},
+    path::PathBuf,
+    process::{self, Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use alloy::primitives::U256;
+use bonsai_sdk::{
+    blocking::{Client, SessionId},
+    responses::SessionStats,
+    SdkErr,
+};
+use clap::ValueEnum;
 use eyre::Result;
 use garaga_rs::{
     calldata::full_proof_with_hints::groth16::{
@@ -5,49 +28,807 @@ use garaga_rs::{
     },
     definitions::CurveID,
 };
-use methods::STORAGE_INCLUSION_ELF;
+use methods::{STORAGE_INCLUSION_ELF, STORAGE_INCLUSION_ID};
 use risc0_ethereum_contracts::encode_seal;
-use risc0_zkvm::{compute_image_id, default_prover, ExecutorEnv, ProverOpts};
+use risc0_zkvm::{
+    serde::to_vec, sha::Digest, Executor, ExecutorEnv, ExternalProver, InnerReceipt, Journal,
+    Prover as _, ProverOpts, Receipt,
+};
+use serde::{Deserialize, Serialize};
 use starknet::core::types::Felt;
-use tokio::task;
-use types::ProverInput;
+use tokio::{
+    sync::Mutex,
+    task::{self, JoinHandle},
+};
+use types::{ProverInput, ProverOutput};
 
-#[derive(Debug, Clone)]
-pub struct Groth16 {
+use crate::{
+    error::{InputError, RelayerError},
+    util::write_atomic,
+};
+
+/// Interval between two Bonsai status requests
+const BONSAI_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Interval between two checks of a local proving process
+const LOCAL_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// First argument running the relayer as a local proving process, see
+/// [`run_local_job`]
+pub const LOCAL_JOB_ARG: &str = "__prove-local";
+
+/// Everything produced by proving a block
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProofArtifact {
+    pub block_number: u64,
+    /// World ID root committed to the journal
+    pub root: U256,
+    /// Image id of the storage inclusion guest
+    pub image_id: Digest,
+    pub journal: Vec<u8>,
+    /// Seal encoded for on-chain verification, empty for mock proofs
+    pub seal: Vec<u8>,
+    /// `None` for mock proofs
+    pub receipt: Option<Receipt>,
+    /// Calldata of `verify_latest_root_proof`
     pub calldata: Vec<Felt>,
 }
 
+impl ProofArtifact {
+    /// Check that the artifact proves `root` at `block_number`: its journal
+    /// must commit to them and its receipt, unless fake, must verify against
+    /// the guest image.
+    pub fn verify(&self, block_number: u64, root: U256) -> Result<(), RelayerError> {
+        check_journal(&Journal::new(self.journal.clone()), block_number, root)?;
+
+        let Some(receipt) = &self.receipt else {
+            return Ok(());
+        };
+        if receipt.journal.bytes != self.journal {
+            return Err(RelayerError::prover(
+                "Invalid proof artifact",
+                "receipt journal differs from the artifact journal",
+            ));
+        }
+        if !matches!(receipt.inner, InnerReceipt::Fake(_)) {
+            receipt
+                .verify(STORAGE_INCLUSION_ID)
+                .map_err(|e| RelayerError::prover("Proof artifact receipt does not verify", e))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Generates the proof relayed for a prover input
+pub trait Prover: Clone + Send + Sync + 'static {
+    /// Kind of proof produced, artifacts are only reused by provers of the
+    /// same kind
+    fn kind(&self) -> &'static str;
+
+    /// Whether the guest should be executed as a preflight before proving
+    fn needs_preflight(&self) -> bool {
+        true
+    }
+
+    /// Run the guest on the input without proving it, by default in the
+    /// local executor
+    fn execute(&self, input: ProverInput) -> impl Future<Output = Result<ExecutionReport>> + Send {
+        execute_locally(input)
+    }
+
+    fn prove(&self, input: ProverInput) -> impl Future<Output = Result<ProofArtifact>> + Send;
+}
+
+/// Proving backend selected on the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProverBackend {
+    /// Prove on this machine through the `r0vm` server
+    Local,
+    /// Prove remotely on Bonsai
+    Bonsai,
+    /// Execute the guest locally and emit a fake receipt, for the mock
+    /// verifier
+    Dev,
+    /// Skip the guest, the calldata only carries the expected journal, for
+    /// the mock verifier
+    Mock,
+}
+
+/// Where the risc0 proof is generated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Risc0Backend {
+    Local,
+    Bonsai,
+    /// Local execution with fake receipts
+    Dev,
+}
+
+/// Groth16 prover of the storage inclusion guest
 #[derive(Debug, Clone)]
-pub struct Risc0Prover {}
+pub struct Risc0Prover {
+    backend: Risc0Backend,
+    slot: ProvingSlot<ProofArtifact>,
+}
 
 impl Risc0Prover {
-    pub async fn prove(&self, input: ProverInput) -> Result<Groth16> {
-        tracing::info!("Starting proof generation");
-
-        task::spawn_blocking(move || {
-            let env = ExecutorEnv::builder()
-                .write(&input)
-                .expect("failed to write the prover input")
-                .build()
-                .unwrap();
-
-            let prover = default_prover();
-            let receipt = prover
-                .prove_with_opts(env, STORAGE_INCLUSION_ELF, &ProverOpts::groth16())
-                .unwrap()
-                .receipt;
-
-            tracing::info!("Proof generated");
-            let seal = encode_seal(&receipt).unwrap();
-            let image_id = compute_image_id(STORAGE_INCLUSION_ELF).unwrap();
-            let journal = receipt.journal.bytes.clone();
-
-            let proof = Groth16Proof::from_risc0(seal, image_id.as_bytes().to_vec(), journal);
-            let calldata =
-                get_groth16_calldata_felt(&proof, &get_risc0_vk(), CurveID::BN254).unwrap();
-
-            Ok(Groth16 { calldata })
+    pub fn local() -> Self {
+        Self::new(Risc0Backend::Local)
+    }
+
+    /// Prover using the `BONSAI_API_URL` and `BONSAI_API_KEY` credentials
+    pub fn bonsai() -> Self {
+        Self::new(Risc0Backend::Bonsai)
+    }
+
+    /// Prover executing the guest without proving it. Its receipts are only
+    /// accepted by the mock verifier.
+    pub fn dev() -> Self {
+        Self::new(Risc0Backend::Dev)
+    }
+
+    fn new(backend: Risc0Backend) -> Self {
+        Self {
+            backend,
+            slot: ProvingSlot::default(),
+        }
+    }
+
+    /// Prove the input, giving up as soon as `cancelled` is set
+    fn prove_blocking(
+        &self,
+        input: ProverInput,
+        cancelled: &AtomicBool,
+    ) -> Result<ProofArtifact, RelayerError> {
+        let block_number = input.header.number;
+        let root = input.account_proof.storage_proof.value;
+        let dev_mode = self.backend == Risc0Backend::Dev;
+
+        let receipt = match self.backend {
+            Risc0Backend::Bonsai => bonsai_prove(&input, cancelled)?,
+            Risc0Backend::Local | Risc0Backend::Dev => prove_locally(&input, dev_mode, cancelled)?,
+        };
+        if cancelled.load(Ordering::Relaxed) {
+            return Err(RelayerError::Cancelled);
+        }
+        // Only local proofs get a preflight, every receipt is checked here
+        check_journal(&receipt.journal, block_number, root)?;
+
+        tracing::info!("Proof generated");
+        let seal = encode_seal(&receipt)
+            .map_err(|e| RelayerError::calldata("Failed to encode the seal", e))?;
+        let image_id = Digest::from(STORAGE_INCLUSION_ID);
+        let journal = receipt.journal.bytes.clone();
+
+        let calldata = if dev_mode {
+            mock_calldata(&journal)
+        } else {
+            let proof = Groth16Proof::from_risc0(
+                seal.clone(),
+                image_id.as_bytes().to_vec(),
+                journal.clone(),
+            );
+            get_groth16_calldata_felt(&proof, &get_risc0_vk(), CurveID::BN254)
+                .map_err(|e| RelayerError::calldata("Failed to build the garaga calldata", e))?
+        };
+
+        Ok(ProofArtifact {
+            block_number,
+            root,
+            image_id,
+            journal,
+            seal,
+            receipt: Some(receipt),
+            calldata,
+        })
+    }
+}
+
+impl Prover for Risc0Prover {
+    fn kind(&self) -> &'static str {
+        match self.backend {
+            Risc0Backend::Local | Risc0Backend::Bonsai => "groth16",
+            Risc0Backend::Dev => "dev",
+        }
+    }
+
+    /// Dev mode already executes the guest, and a Bonsai session fails as
+    /// early as an execute-only one would
+    fn needs_preflight(&self) -> bool {
+        self.backend == Risc0Backend::Local
+    }
+
+    async fn execute(&self, input: ProverInput) -> Result<ExecutionReport> {
+        if self.backend != Risc0Backend::Bonsai {
+            return execute_locally(input).await;
+        }
+
+        input.verify().map_err(RelayerError::from)?;
+        let report = task::spawn_blocking(move || bonsai_execute(&input)).await??;
+
+        Ok(report)
+    }
+
+    async fn prove(&self, input: ProverInput) -> Result<ProofArtifact> {
+        // Fail fast on inputs the guest would reject
+        input.verify().map_err(RelayerError::from)?;
+
+        tracing::info!("Starting proof generation ({:?})", self.backend);
+
+        // Dropping this future, on timeout or shutdown, cancels the job
+        let this = self.clone();
+        self.slot
+            .run(move |cancelled| this.prove_blocking(input, cancelled))
+            .await
+    }
+}
+
+/// Sets the shared flag when dropped
+#[derive(Debug, Default)]
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Runs one proving job at a time.
+///
+/// Dropping the future of a job, on timeout or shutdown, cancels it: the local
+/// prover process is killed and the Bonsai session stopped. The next job only
+/// waits for the cancelled one to stop, never for it to complete.
+#[derive(Debug)]
+struct ProvingSlot<T> {
+    job: Arc<Mutex<Option<JoinHandle<Result<T, RelayerError>>>>>,
+}
+
+impl<T> Clone for ProvingSlot<T> {
+    fn clone(&self) -> Self {
+        Self {
+            job: self.job.clone(),
+        }
+    }
+}
+
+impl<T> Default for ProvingSlot<T> {
+    fn default() -> Self {
+        Self {
+            job: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
+impl<T: Send + 'static> ProvingSlot<T> {
+    /// Run `job` on a blocking thread once the previous job stopped. Dropping
+    /// the returned future sets the flag `job` receives.
+    async fn run<F>(&self, job: F) -> Result<T>
+    where
+        F: FnOnce(&AtomicBool) -> Result<T, RelayerError> + Send + 'static,
+    {
+        let mut slot = self.job.lock().await;
+        if let Some(cancelled) = slot.as_mut() {
+            tracing::info!("Waiting for the cancelled proving job to stop");
+            let _ = cancelled.await;
+            *slot = None;
+        }
+
+        let cancel = CancelOnDrop::default();
+        let flag = cancel.0.clone();
+        let handle = slot.insert(task::spawn_blocking(move || job(&flag)));
+        let result = handle.await;
+        *slot = None;
+
+        Ok(result??)
+    }
+}
+
+/// Prover returning the journal the guest would commit, without running it
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MockProver;
+
+impl Prover for MockProver {
+    fn kind(&self) -> &'static str {
+        "mock"
+    }
+
+    fn needs_preflight(&self) -> bool {
+        false
+    }
+
+    async fn prove(&self, input: ProverInput) -> Result<ProofArtifact> {
+        input.verify().map_err(RelayerError::from)?;
+
+        let output = ProverOutput {
+            block_number: input.header.number,
+            state_root: input.account_proof.storage_proof.value,
+        };
+        tracing::info!("Mock proof of block {}", output.block_number);
+
+        let journal: Vec<u8> = to_vec(&output)
+            .map_err(|e| RelayerError::prover("Failed to encode the journal", e))?
+            .into_iter()
+            .flat_map(u32::to_le_bytes)
+            .collect();
+
+        Ok(ProofArtifact {
+            block_number: output.block_number,
+            root: output.state_root,
+            image_id: Digest::from(STORAGE_INCLUSION_ID),
+            calldata: mock_calldata(&journal),
+            journal,
+            seal: Vec::new(),
+            receipt: None,
+        })
+    }
+}
+
+/// Outcome of running the guest in the executor only
+#[derive(Debug, Clone)]
+pub struct ExecutionReport {
+    pub block_number: u64,
+    pub root: U256,
+    pub segments: usize,
+    /// Cycles spent running the guest
+    pub user_cycles: u64,
+    /// Cycles to prove, each segment being padded to a power of two
+    pub total_cycles: u64,
+    pub journal: Vec<u8>,
+}
+
+impl fmt::Display for ExecutionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Execution of block {}", self.block_number)?;
+        writeln!(f, "  root:         {:#x}", self.root)?;
+        writeln!(f, "  segments:     {}", self.segments)?;
+        writeln!(f, "  user cycles:  {}", self.user_cycles)?;
+        writeln!(f, "  total cycles: {}", self.total_cycles)?;
+        write!(f, "  journal:      {} bytes", self.journal.len())
+    }
+}
+
+/// Run the guest on the input in the local executor, without proving it, and
+/// check its journal commits to the block and root of the input
+pub async fn execute_locally(input: ProverInput) -> Result<ExecutionReport> {
+    input.verify().map_err(RelayerError::from)?;
+
+    let report = task::spawn_blocking(move || -> Result<_, RelayerError> {
+        let block_number = input.header.number;
+        let root = input.account_proof.storage_proof.value;
+        let env = executor_env(&input)?;
+
+        let session = local_prover()
+            .execute(env, STORAGE_INCLUSION_ELF)
+            .map_err(|e| RelayerError::prover("Execution failed", e))?;
+        check_journal(&session.journal, block_number, root)?;
+
+        Ok(ExecutionReport {
+            block_number,
+            root,
+            segments: session.segments.len(),
+            user_cycles: session.segments.iter().map(|s| s.cycles as u64).sum(),
+            total_cycles: session.segments.iter().map(|s| 1u64 << s.po2).sum(),
+            journal: session.journal.bytes,
         })
-        .await?
+    })
+    .await??;
+
+    Ok(report)
+}
+
+/// Check the journal commits to the block and root of the input
+fn check_journal(journal: &Journal, block_number: u64, root: U256) -> Result<(), RelayerError> {
+    let output: ProverOutput = journal
+        .decode()
+        .map_err(|e| RelayerError::prover("Invalid journal", e))?;
+    if (output.block_number, output.state_root) != (block_number, root) {
+        return Err(InputError::JournalMismatch {
+            block_number,
+            root,
+            journal_block: output.block_number,
+            journal_root: output.state_root,
+        }
+        .into());
+    }
+
+    Ok(())
+}
+
+fn bonsai_error(e: SdkErr) -> RelayerError {
+    RelayerError::prover("Bonsai request failed", e)
+}
+
+/// Upload the guest and the input, then start a Bonsai session
+fn bonsai_session(
+    client: &Client,
+    input: &ProverInput,
+    execute_only: bool,
+) -> Result<SessionId, RelayerError> {
+    let image_id = Digest::from(STORAGE_INCLUSION_ID).to_string();
+    client
+        .upload_img(&image_id, STORAGE_INCLUSION_ELF.to_vec())
+        .map_err(bonsai_error)?;
+    let input: Vec<u8> = to_vec(input)
+        .map_err(|e| RelayerError::prover("Failed to encode the input", e))?
+        .into_iter()
+        .flat_map(u32::to_le_bytes)
+        .collect();
+    let input_id = client.upload_input(input).map_err(bonsai_error)?;
+
+    let session = client
+        .create_session(image_id, input_id, vec![], execute_only)
+        .map_err(bonsai_error)?;
+    tracing::info!("Bonsai session {}", session.uuid);
+
+    Ok(session)
+}
+
+/// State of a remote Bonsai job
+enum Remote<T> {
+    Running,
+    Succeeded(T),
+}
+
+/// Poll a remote job until it succeeds. Once `cancelled` is set, the job is
+/// stopped and [`RelayerError::Cancelled`] returned.
+fn wait_remote<T>(
+    cancelled: &AtomicBool,
+    interval: Duration,
+    mut poll: impl FnMut() -> Result<Remote<T>, RelayerError>,
+    stop: impl FnOnce() -> Result<(), RelayerError>,
+) -> Result<T, RelayerError> {
+    loop {
+        if cancelled.load(Ordering::Relaxed) {
+            stop()?;
+            return Err(RelayerError::Cancelled);
+        }
+
+        match poll()? {
+            Remote::Running => thread::sleep(interval),
+            Remote::Succeeded(value) => return Ok(value),
+        }
+    }
+}
+
+/// Wait for a Bonsai session to succeed, stopping it once `cancelled` is set
+fn wait_session(
+    client: &Client,
+    session: &SessionId,
+    cancelled: &AtomicBool,
+) -> Result<Option<SessionStats>, RelayerError> {
+    wait_remote(
+        cancelled,
+        BONSAI_POLL_INTERVAL,
+        || {
+            let status = session.status(client).map_err(bonsai_error)?;
+            match status.status.as_str() {
+                "RUNNING" => Ok(Remote::Running),
+                "SUCCEEDED" => Ok(Remote::Succeeded(status.stats)),
+                other => Err(RelayerError::prover(
+                    format!("Bonsai session {} {other}", session.uuid),
+                    status.error_msg.unwrap_or_default(),
+                )),
+            }
+        },
+        || {
+            session.stop(client).map_err(bonsai_error)?;
+            tracing::warn!("Bonsai session {} stopped", session.uuid);
+            Ok(())
+        },
+    )
+}
+
+/// Run the guest on Bonsai without proving it
+fn bonsai_execute(input: &ProverInput) -> Result<ExecutionReport, RelayerError> {
+    let block_number = input.header.number;
+    let root = input.account_proof.storage_proof.value;
+    let client = Client::from_env(risc0_zkvm::VERSION).map_err(bonsai_error)?;
+
+    let session = bonsai_session(&client, input, true)?;
+    let stats = wait_session(&client, &session, &AtomicBool::new(false))?;
+    let stats = stats.ok_or_else(|| {
+        RelayerError::prover(format!("Bonsai session {}", session.uuid), "no stats")
+    })?;
+    let journal = Journal::new(session.exec_only_journal(&client).map_err(bonsai_error)?);
+    check_journal(&journal, block_number, root)?;
+
+    Ok(ExecutionReport {
+        block_number,
+        root,
+        segments: stats.segments,
+        user_cycles: stats.cycles,
+        total_cycles: stats.total_cycles,
+        journal: journal.bytes,
+    })
+}
+
+/// Prove the input on Bonsai, stopping the session once `cancelled` is set
+fn bonsai_prove(input: &ProverInput, cancelled: &AtomicBool) -> Result<Receipt, RelayerError> {
+    let client = Client::from_env(risc0_zkvm::VERSION).map_err(bonsai_error)?;
+
+    let session = bonsai_session(&client, input, false)?;
+    wait_session(&client, &session, cancelled)?;
+
+    let snark = client.create_snark(session.uuid).map_err(bonsai_error)?;
+    tracing::info!("Bonsai SNARK session {}", snark.uuid);
+    let url = wait_remote(
+        cancelled,
+        BONSAI_POLL_INTERVAL,
+        || {
+            let status = snark.status(&client).map_err(bonsai_error)?;
+            match status.status.as_str() {
+                "RUNNING" => Ok(Remote::Running),
+                "SUCCEEDED" => Ok(Remote::Succeeded(status.output)),
+                other => Err(RelayerError::prover(
+                    format!("Bonsai SNARK {} {other}", snark.uuid),
+                    status.error_msg.unwrap_or_default(),
+                )),
+            }
+        },
+        // SNARK sessions cannot be stopped, only abandoned
+        || Ok(()),
+    )?
+    .ok_or_else(|| RelayerError::prover(format!("Bonsai SNARK {}", snark.uuid), "no output URL"))?;
+
+    // Receipts are serialized with bincode by the Bonsai API, pinned along
+    // with the SDK version
+    let receipt = client.download(&url).map_err(bonsai_error)?;
+    let receipt: Receipt = bincode::deserialize(&receipt)
+        .map_err(|e| RelayerError::prover("Invalid Bonsai receipt", e))?;
+    receipt
+        .verify(STORAGE_INCLUSION_ID)
+        .map_err(|e| RelayerError::prover("Bonsai receipt verification failed", e))?;
+
+    Ok(receipt)
+}
+
+/// Executor environment holding the prover input
+fn executor_env(input: &ProverInput) -> Result<ExecutorEnv<'static>, RelayerError> {
+    ExecutorEnv::builder()
+        .write(input)
+        .and_then(|builder| builder.build())
+        .map_err(|e| RelayerError::prover("Invalid executor env", e))
+}
+
+/// Prover and executor backed by the local `r0vm` server
+fn local_prover() -> ExternalProver {
+    let r0vm = std::env::var("RISC0_SERVER_PATH").unwrap_or_else(|_| "r0vm".into());
+    ExternalProver::new("local", r0vm)
+}
+
+/// Prove in a child process running [`run_local_job`]. Once `cancelled` is
+/// set, the process is killed along with its r0vm server.
+fn prove_locally(
+    input: &ProverInput,
+    dev_mode: bool,
+    cancelled: &AtomicBool,
+) -> Result<Receipt, RelayerError> {
+    let receipt_path = env::temp_dir().join(format!(
+        "world-relayer-{}-{}.receipt",
+        process::id(),
+        input.header.number
+    ));
+    let exe = env::current_exe()
+        .map_err(|e| RelayerError::prover("Failed to locate the relayer binary", e))?;
+
+    let mut command = Command::new(exe);
+    command
+        .arg(LOCAL_JOB_ARG)
+        .arg(&receipt_path)
+        .stdin(Stdio::piped());
+    if dev_mode {
+        command.arg("dev");
+    }
+    // Leads a process group shared with the r0vm server it starts
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    let mut child = command
+        .spawn()
+        .map_err(|e| RelayerError::prover("Failed to start the prover process", e))?;
+
+    // The input fills the first line, the pipe then stays open until the job
+    // ends so the process never outlives the relayer
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let sent = serde_json::to_writer(&mut stdin, input)
+        .map_err(io::Error::from)
+        .and_then(|()| stdin.write_all(b"\n"));
+    if let Err(e) = sent {
+        kill_local_job(&mut child);
+        return Err(RelayerError::prover("Failed to send the prover input", e));
+    }
+
+    // Killed below once cancelled, the poll borrows the child until then
+    let status = wait_remote(
+        cancelled,
+        LOCAL_POLL_INTERVAL,
+        || match child.try_wait() {
+            Ok(None) => Ok(Remote::Running),
+            Ok(Some(status)) => Ok(Remote::Succeeded(status)),
+            Err(e) => Err(RelayerError::prover("Lost the prover process", e)),
+        },
+        || Ok(()),
+    );
+    let status = match status {
+        Ok(status) => status,
+        Err(e) => {
+            kill_local_job(&mut child);
+            let _ = fs::remove_file(&receipt_path);
+            return Err(e);
+        }
+    };
+    drop(stdin);
+
+    let receipt = if status.success() {
+        fs::read(&receipt_path)
+            .map_err(|e| RelayerError::prover("Failed to read the local receipt", e))
+    } else {
+        Err(RelayerError::prover(
+            "Proving failed",
+            format!("prover process {status}"),
+        ))
+    };
+    let _ = fs::remove_file(&receipt_path);
+    let receipt = receipt?;
+
+    bincode::deserialize(&receipt).map_err(|e| RelayerError::prover("Invalid local receipt", e))
+}
+
+/// Kill a local proving process along with the r0vm server it started
+fn kill_local_job(child: &mut Child) {
+    // The child leads its own process group, see `prove_locally`
+    #[cfg(unix)]
+    unsafe {
+        libc::killpg(child.id() as libc::pid_t, libc::SIGKILL);
+    }
+    #[cfg(not(unix))]
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// Entry point of a local proving process started by [`prove_locally`].
+///
+/// Proves the input read from the first line of stdin and writes the receipt
+/// to the path given as first argument. The whole process group, r0vm server
+/// included, is killed as soon as stdin closes.
+pub fn run_local_job(mut args: impl Iterator<Item = OsString>) -> Result<()> {
+    let receipt_path = args
+        .next()
+        .map(PathBuf::from)
+        .ok_or_else(|| eyre::eyre!("Missing receipt path"))?;
+    let dev_mode = args.next().is_some_and(|arg| arg == "dev");
+
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    let input: ProverInput = serde_json::from_str(&line)?;
+
+    // Closed by the relayer once it gave up on the job, or exited
+    thread::spawn(|| {
+        let _ = io::copy(&mut io::stdin(), &mut io::sink());
+        #[cfg(unix)]
+        unsafe {
+            libc::killpg(0, libc::SIGKILL);
+        }
+        process::exit(1);
+    });
+
+    let env = executor_env(&input)?;
+    let opts = ProverOpts::groth16().with_dev_mode(dev_mode);
+    let receipt = local_prover()
+        .prove_with_opts(env, STORAGE_INCLUSION_ELF, &opts)
+        .map_err(|e| RelayerError::prover("Proving failed", e))?
+        .receipt;
+    write_atomic(&receipt_path, &bincode::serialize(&receipt)?)?;
+
+    Ok(())
+}
+
+/// Calldata accepted by a verifier deployed against the mock Groth16
+/// verifier. Laid out like the garaga calldata, with the journal bytes in
+/// place of the proof: span length, the felt dropped by the verifier, then one
+/// felt per journal byte.
+fn mock_calldata(journal: &[u8]) -> Vec<Felt> {
+    let mut calldata = Vec::with_capacity(journal.len() + 2);
+    calldata.push(Felt::from(journal.len() + 1));
+    calldata.push(Felt::from(journal.len()));
+    calldata.extend(journal.iter().map(|byte| Felt::from(*byte)));
+
+    calldata
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_calldata_encodes_journal_bytes() {
+        let calldata = mock_calldata(&[7, 0, 255]);
+
+        assert_eq!(calldata, [4u8, 3, 7, 0, 255].map(Felt::from).to_vec());
+    }
+
+    #[test]
+    fn mock_calldata_of_empty_journal() {
+        assert_eq!(mock_calldata(&[]), vec![Felt::from(1u8), Felt::ZERO]);
+    }
+
+    #[tokio::test]
+    async fn dropped_job_is_cancelled_before_next_one() {
+        let slot = ProvingSlot::default();
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let first = {
+            let stopped = stopped.clone();
+            move |cancelled: &AtomicBool| {
+                while !cancelled.load(Ordering::Relaxed) {
+                    thread::sleep(Duration::from_millis(5));
+                }
+                stopped.store(true, Ordering::Relaxed);
+                Err(RelayerError::Cancelled)
+            }
+        };
+        let run = slot.run(first);
+        assert!(tokio::time::timeout(Duration::from_millis(20), run)
+            .await
+            .is_err());
+
+        let value = slot
+            .run(move |_| {
+                assert!(stopped.load(Ordering::Relaxed), "jobs ran concurrently");
+                Ok(2)
+            })
+            .await
+            .unwrap();
+        assert_eq!(value, 2);
+    }
+
+    #[tokio::test]
+    async fn failed_job_frees_the_slot() {
+        let slot = ProvingSlot::default();
+
+        let result = slot
+            .run(|_| Err::<u64, _>(RelayerError::prover("Proving failed", "boom")))
+            .await;
+        assert!(result.is_err());
+
+        assert_eq!(slot.run(|_| Ok(2)).await.unwrap(), 2);
+    }
+
+    #[test]
+    fn cancelled_remote_job_is_stopped() {
+        let mut stopped = false;
+
+        let result = wait_remote::<()>(
+            &AtomicBool::new(true),
+            Duration::ZERO,
+            || panic!("polled a cancelled job"),
+            || {
+                stopped = true;
+                Ok(())
+            },
+        );
+
+        assert!(matches!(result, Err(RelayerError::Cancelled)));
+        assert!(stopped);
+    }
+
+    #[test]
+    fn remote_job_polled_until_success() {
+        let mut polls = 0;
+
+        let result = wait_remote(
+            &AtomicBool::new(false),
+            Duration::ZERO,
+            || {
+                polls += 1;
+                Ok(if polls < 3 {
+                    Remote::Running
+                } else {
+                    Remote::Succeeded(polls)
+                })
+            },
+            || panic!("stopped a running job"),
+        );
+
+        assert_eq!(result.unwrap(), 3);
     }
 }
@@ -0,0 +1,169 @@
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use alloy::providers::{DynProvider, Provider, ProviderBuilder};
+use eyre::Result;
+use futures_util::future::join_all;
+
+/// Ethereum provider backed by several endpoints.
+///
+/// Requests fail over to the next endpoint on error. In quorum mode, requests
+/// issued through [`EthProvider::consensus`] are sent to every endpoint and
+/// only succeed when a majority of them return the same result.
+#[derive(Debug, Clone)]
+pub struct EthProvider {
+    endpoints: Vec<DynProvider>,
+    quorum: bool,
+    /// Index of the last endpoint that answered successfully
+    preferred: Arc<AtomicUsize>,
+}
+
+impl EthProvider {
+    pub async fn connect(urls: &[String], quorum: bool) -> Result<Self> {
+        if urls.is_empty() {
+            return Err(eyre::eyre!("At least one Ethereum RPC URL is required"));
+        }
+
+        let mut endpoints = Vec::with_capacity(urls.len());
+        for url in urls {
+            endpoints.push(ProviderBuilder::new().on_builtin(url).await?.erased());
+        }
+
+        Ok(Self {
+            endpoints,
+            quorum,
+            preferred: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Whether results must be agreed upon by a majority of endpoints
+    pub fn is_quorum(&self) -> bool {
+        self.quorum && self.endpoints.len() > 1
+    }
+
+    /// Send the request to the endpoints in turn, starting from the last
+    /// healthy one, until one of them succeeds.
+    pub async fn failover<T, F, Fut>(&self, request: F) -> Result<T>
+    where
+        F: Fn(DynProvider) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let start = self.preferred.load(Ordering::Relaxed);
+        let mut last_error = None;
+
+        for offset in 0..self.endpoints.len() {
+            let index = (start + offset) % self.endpoints.len();
+            match request(self.endpoints[index].clone()).await {
+                Ok(value) => {
+                    self.preferred.store(index, Ordering::Relaxed);
+                    return Ok(value);
+                }
+                Err(e) => {
+                    if self.endpoints.len() > 1 {
+                        tracing::warn!("Ethereum endpoint {index} failed: {}", e);
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| eyre::eyre!("No Ethereum endpoint configured")))
+    }
+
+    /// Send the request to every endpoint and return the result shared by a
+    /// majority of them. Falls back to [`EthProvider::failover`] when quorum
+    /// mode is disabled.
+    pub async fn consensus<T, F, Fut>(&self, request: F) -> Result<T>
+    where
+        T: PartialEq,
+        F: Fn(DynProvider) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        if !self.is_quorum() {
+            return self.failover(request).await;
+        }
+
+        let responses = join_all(self.endpoints.iter().cloned().map(request)).await;
+
+        majority(responses)
+    }
+}
+
+/// Result shared by a majority of the endpoint responses
+fn majority<T: PartialEq>(responses: Vec<Result<T>>) -> Result<T> {
+    let endpoints = responses.len();
+
+    // Group identical answers, keeping the first error for reporting
+    let mut answers: Vec<(T, usize)> = Vec::new();
+    let mut first_error = None;
+    for (index, response) in responses.into_iter().enumerate() {
+        match response {
+            Ok(value) => match answers.iter_mut().find(|(answer, _)| *answer == value) {
+                Some((_, votes)) => *votes += 1,
+                None => answers.push((value, 1)),
+            },
+            Err(e) => {
+                tracing::warn!("Ethereum endpoint {index} failed: {}", e);
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    let majority = endpoints / 2 + 1;
+    if let Some(position) = answers.iter().position(|(_, votes)| *votes >= majority) {
+        return Ok(answers.swap_remove(position).0);
+    }
+
+    match (answers.is_empty(), first_error) {
+        (true, Some(e)) => Err(e),
+        _ => Err(eyre::eyre!(
+            "No quorum among {endpoints} Ethereum endpoints ({} distinct answers)",
+            answers.len()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn majority_answer_wins() {
+        let responses = vec![Ok(1), Ok(2), Ok(1)];
+
+        assert_eq!(majority(responses).unwrap(), 1);
+    }
+
+    #[test]
+    fn failed_endpoints_count_against_majority() {
+        let responses = vec![Ok(1), Err(eyre::eyre!("timeout")), Ok(1)];
+        assert_eq!(majority(responses).unwrap(), 1);
+
+        let responses = vec![
+            Ok(1),
+            Err(eyre::eyre!("timeout")),
+            Err(eyre::eyre!("timeout")),
+        ];
+        assert!(majority(responses).is_err());
+    }
+
+    #[test]
+    fn split_answers_have_no_quorum() {
+        let error = majority(vec![Ok(1), Ok(2), Ok(3), Ok(1)]).unwrap_err();
+
+        assert!(error.to_string().contains("No quorum among 4"), "{error}");
+    }
+
+    #[test]
+    fn all_failing_endpoints_return_first_error() {
+        let responses: Vec<Result<u64>> =
+            vec![Err(eyre::eyre!("first")), Err(eyre::eyre!("second"))];
+
+        assert_eq!(majority(responses).unwrap_err().to_string(), "first");
+    }
+}
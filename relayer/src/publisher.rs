@@ -1,44 +1,162 @@
-use std::str::FromStr;
+use std::{collections::HashSet, fs, path::Path, str::FromStr, time::Duration};
 
+use alloy::primitives::U256;
 use alloy_chains::NamedChain;
-use eyre::Result;
+use eyre::{Result, WrapErr};
+use futures_util::future::try_join_all;
+use serde::Deserialize;
 use starknet::{
-    accounts::{Account, ExecutionEncoding, SingleOwnerAccount},
+    accounts::{Account, AccountError, ConnectedAccount, ExecutionEncoding, SingleOwnerAccount},
     core::{
         chain_id,
-        types::{Call, Felt},
-        utils::get_selector_from_name,
+        types::{
+            BlockId, BlockTag, Call, ExecutionResult, Felt, FunctionCall, ReceiptBlock,
+            StarknetError,
+        },
+        utils::{cairo_short_string_to_felt, get_selector_from_name},
     },
-    providers::{jsonrpc::HttpTransport, JsonRpcClient, Url},
+    providers::{jsonrpc::HttpTransport, JsonRpcClient, Provider, ProviderError, Url},
     signers::{LocalWallet, SigningKey},
 };
 
-use crate::prover::Groth16;
+use crate::{
+    error::{PublisherError, RelayerError},
+    prover::ProofArtifact,
+};
+
+/// Interval between two receipt lookups of a submitted transaction
+const CONFIRMATION_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Receipt lookups before giving up on a submitted transaction
+const CONFIRMATION_POLLS: u32 = 120;
+
+/// Latest root held by the `WorldRelayerStore` contract
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoredRoot {
+    pub root: U256,
+    pub block_number: u64,
+}
+
+impl StoredRoot {
+    /// Whether relaying `root` at `block_number` would not change the store.
+    /// The store accepts the same block again, so another root at the stored
+    /// block still replaces one orphaned by a reorg.
+    pub fn covers(&self, block_number: u64, root: Option<U256>) -> bool {
+        self.block_number > block_number
+            || (self.block_number == block_number && root.is_none_or(|root| root == self.root))
+    }
+}
+
+/// Starknet deployment of the verifier and store contracts
+#[derive(Debug, Clone, Deserialize)]
+pub struct DestinationConfig {
+    /// Name of the destination in logs and job statuses
+    pub name: String,
+    pub rpc_url: String,
+    /// `SN_MAIN`, `SN_SEPOLIA`, or the chain id of an appchain as a short
+    /// string or hex felt
+    pub chain_id: String,
+    pub account: String,
+    pub private_key: String,
+    /// Address of the WorldRelayerVerifier contract
+    pub verifier: String,
+}
+
+impl DestinationConfig {
+    /// Read a JSON list of destinations
+    pub fn load(path: impl AsRef<Path>) -> Result<Vec<Self>> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read destinations {}", path.display()))?;
+
+        serde_json::from_str(&content)
+            .wrap_err_with(|| format!("Invalid destinations {}", path.display()))
+    }
+}
+
+/// Starknet chain id of an Ethereum chain the verifier is deployed for
+pub fn starknet_chain_id(chain: &alloy_chains::Chain) -> Result<&'static str> {
+    match chain.named() {
+        Some(NamedChain::Mainnet) => Ok("SN_MAIN"),
+        Some(NamedChain::Sepolia) => Ok("SN_SEPOLIA"),
+        _ => Err(RelayerError::from(PublisherError::UnsupportedChain(*chain)).into()),
+    }
+}
 
+/// Publishes each proof to every destination
 #[derive(Debug, Clone)]
 pub struct ProofPublisher {
+    destinations: Vec<Destination>,
+}
+
+impl ProofPublisher {
+    pub fn new(destinations: &[DestinationConfig]) -> Result<Self> {
+        if destinations.is_empty() {
+            return Err(eyre::eyre!("At least one Starknet destination is required"));
+        }
+
+        // Job records track deliveries by destination name
+        let mut names = HashSet::new();
+        if let Some(duplicate) = destinations
+            .iter()
+            .find(|destination| !names.insert(destination.name.as_str()))
+        {
+            return Err(eyre::eyre!(
+                "Duplicate Starknet destination name {}, the primary destination is named after --chain",
+                duplicate.name
+            ));
+        }
+
+        let destinations = destinations
+            .iter()
+            .map(Destination::new)
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { destinations })
+    }
+
+    pub fn destinations(&self) -> &[Destination] {
+        &self.destinations
+    }
+
+    /// Latest root of the destination lagging the most behind
+    pub async fn latest_root_block(&self) -> Result<StoredRoot> {
+        let stored = try_join_all(
+            self.destinations
+                .iter()
+                .map(|destination| destination.latest_root_block()),
+        )
+        .await?;
+
+        stored
+            .into_iter()
+            .min_by_key(|stored| stored.block_number)
+            .ok_or_else(|| eyre::eyre!("No Starknet destination"))
+    }
+}
+
+/// Verifier deployment on a single Starknet chain
+#[derive(Debug, Clone)]
+pub struct Destination {
+    name: String,
     account: SingleOwnerAccount<JsonRpcClient<HttpTransport>, LocalWallet>,
     relayer_verifier: Felt,
 }
 
-impl ProofPublisher {
-    pub fn new(
-        rpc_url: &str,
-        private_key: &str,
-        account_address: &str,
-        world_verifier: &str,
-        chain: &alloy_chains::Chain,
-    ) -> Result<Self> {
-        let provider = JsonRpcClient::new(HttpTransport::new(Url::from_str(rpc_url)?));
-        let signer =
-            LocalWallet::from(SigningKey::from_secret_scalar(Felt::from_hex(private_key)?));
-
-        let account_address = Felt::from_hex(account_address)?;
-        let relayer_verifier = Felt::from_hex(world_verifier)?;
-        let chain = match chain.named().unwrap() {
-            NamedChain::Mainnet => chain_id::MAINNET,
-            NamedChain::Sepolia => chain_id::SEPOLIA,
-            _ => return Err(eyre::eyre!("Unsupported chain for Starknet")),
+impl Destination {
+    pub fn new(config: &DestinationConfig) -> Result<Self> {
+        let provider = JsonRpcClient::new(HttpTransport::new(Url::from_str(&config.rpc_url)?));
+        let signer = LocalWallet::from(SigningKey::from_secret_scalar(Felt::from_hex(
+            &config.private_key,
+        )?));
+
+        let account_address = Felt::from_hex(&config.account)?;
+        let relayer_verifier = Felt::from_hex(&config.verifier)?;
+        let chain = match config.chain_id.as_str() {
+            "SN_MAIN" => chain_id::MAINNET,
+            "SN_SEPOLIA" => chain_id::SEPOLIA,
+            id if id.starts_with("0x") => Felt::from_hex(id)?,
+            id => cairo_short_string_to_felt(id)
+                .map_err(|_| eyre::eyre!("Invalid Starknet chain id {id}"))?,
         };
 
         let account = SingleOwnerAccount::new(
@@ -50,22 +168,154 @@ impl ProofPublisher {
         );
 
         Ok(Self {
+            name: config.name.clone(),
             account,
             relayer_verifier,
         })
     }
 
-    pub async fn publish(&self, proof: &Groth16) -> Result<()> {
-        let selector = get_selector_from_name("verify_latest_root_proof").unwrap();
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Read the latest root and block held by the store behind the verifier
+    pub async fn latest_root_block(&self) -> Result<StoredRoot> {
+        let store = self
+            .call(self.relayer_verifier, "get_world_relayer_store_address")
+            .await?;
+        let store = store
+            .first()
+            .copied()
+            .ok_or_else(|| eyre::eyre!("Empty store address response"))?;
+
+        // (u256, u64) is serialized as [root.low, root.high, block]
+        let response = self.call(store, "get_latest_root_block").await?;
+        let [low, high, block] = response[..] else {
+            return Err(eyre::eyre!(
+                "Unexpected get_latest_root_block response {:?}",
+                response
+            ));
+        };
+
+        let limb = |felt: Felt| {
+            u128::try_from(felt).map_err(|_| eyre::eyre!("Invalid u256 limb {felt:#x}"))
+        };
+        let root = (U256::from(limb(high)?) << 128) | U256::from(limb(low)?);
+        let block_number =
+            u64::try_from(block).map_err(|_| eyre::eyre!("Invalid block number {block:#x}"))?;
+
+        Ok(StoredRoot { root, block_number })
+    }
+
+    /// Call a view function without arguments at the latest Starknet block
+    async fn call(&self, contract_address: Felt, function: &str) -> Result<Vec<Felt>> {
+        let call = FunctionCall {
+            contract_address,
+            entry_point_selector: get_selector_from_name(function)?,
+            calldata: vec![],
+        };
+
+        Ok(self
+            .account
+            .provider()
+            .call(call, BlockId::Tag(BlockTag::Latest))
+            .await?)
+    }
+
+    /// Send the proof to the verifier, returning the transaction hash
+    pub async fn publish(&self, proof: &ProofArtifact) -> Result<Felt> {
+        let selector = get_selector_from_name("verify_latest_root_proof")
+            .map_err(|e| RelayerError::calldata("Invalid verifier selector", e))?;
         let call = Call {
             to: self.relayer_verifier,
             selector,
             calldata: proof.calldata.clone(),
         };
 
-        let txn = self.account.execute_v3(vec![call]).send().await?;
-        tracing::info!("Update latest root transaction {}", txn.transaction_hash);
+        let txn = self
+            .account
+            .execute_v3(vec![call])
+            .send()
+            .await
+            .map_err(|e| self.send_error(e))?;
+        tracing::info!(
+            "Update latest root transaction {} on {}",
+            txn.transaction_hash,
+            self.name
+        );
+
+        Ok(txn.transaction_hash)
+    }
+
+    /// Wait until the transaction is included in a Starknet block, failing if
+    /// it reverted
+    pub async fn wait_for_confirmation(&self, tx_hash: Felt) -> Result<()> {
+        for _ in 0..CONFIRMATION_POLLS {
+            match self
+                .account
+                .provider()
+                .get_transaction_receipt(tx_hash)
+                .await
+            {
+                Ok(receipt) if matches!(receipt.block, ReceiptBlock::Block { .. }) => {
+                    return match receipt.receipt.execution_result() {
+                        ExecutionResult::Succeeded => {
+                            tracing::info!("Transaction {tx_hash:#x} confirmed on {}", self.name);
+                            Ok(())
+                        }
+                        ExecutionResult::Reverted { reason } => {
+                            Err(RelayerError::from(PublisherError::Reverted {
+                                destination: self.name.clone(),
+                                tx_hash,
+                                reason: reason.clone(),
+                            })
+                            .into())
+                        }
+                    };
+                }
+                // Pending or not yet seen by the node
+                Ok(_)
+                | Err(ProviderError::StarknetError(StarknetError::TransactionHashNotFound)) => {}
+                Err(err) => {
+                    return Err(RelayerError::from(PublisherError::Provider {
+                        destination: self.name.clone(),
+                        source: err.into(),
+                    })
+                    .into())
+                }
+            }
+
+            tokio::time::sleep(CONFIRMATION_POLL_INTERVAL).await;
+        }
+
+        Err(RelayerError::from(PublisherError::Unconfirmed {
+            destination: self.name.clone(),
+            tx_hash,
+            waited: CONFIRMATION_POLL_INTERVAL * CONFIRMATION_POLLS,
+        })
+        .into())
+    }
+
+    /// Tell contract rejections, which sending again cannot fix, from node
+    /// and network failures
+    fn send_error<S>(&self, error: AccountError<S>) -> RelayerError
+    where
+        S: std::error::Error + Send + Sync + 'static,
+    {
+        let destination = self.name.clone();
+        let error = match error {
+            AccountError::Provider(ProviderError::StarknetError(
+                e @ (StarknetError::ContractError(_) | StarknetError::TransactionExecutionError(_)),
+            )) => PublisherError::Rejected {
+                destination,
+                reason: format!("{e:?}"),
+            },
+            e => PublisherError::Provider {
+                destination,
+                source: e.into(),
+            },
+        };
 
-        Ok(())
+        error.into()
     }
 }
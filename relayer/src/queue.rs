@@ -0,0 +1,220 @@
+use std::collections::VecDeque;
+
+use clap::ValueEnum;
+
+use crate::listener::ListenerEvent;
+
+/// How queued root changes are handled while the prover is busy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CoalescePolicy {
+    /// Only prove the newest waiting root, the store keeps the latest anyway
+    Newest,
+    /// Prove every root in order
+    Every,
+}
+
+/// Queue of listener events waiting to be relayed
+#[derive(Debug)]
+pub struct RootQueue {
+    policy: CoalescePolicy,
+    events: VecDeque<ListenerEvent>,
+    /// Relayable events dropped in favor of a newer one
+    superseded: Vec<ListenerEvent>,
+}
+
+impl RootQueue {
+    pub fn new(policy: CoalescePolicy) -> Self {
+        Self {
+            policy,
+            events: VecDeque::new(),
+            superseded: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn push(&mut self, event: ListenerEvent) {
+        self.events.push_back(event);
+    }
+
+    /// Pop the next event to handle.
+    ///
+    /// With the [`CoalescePolicy::Newest`] policy, every event queued before
+    /// the relayable root of the highest block is superseded by it and
+    /// dropped. Events queued after it, such as the ones of lower blocks
+    /// re-emitted after a reorg, are kept. Dropped relayable events are kept
+    /// for [`Self::take_superseded`].
+    pub fn pop(&mut self, relayable: impl Fn(&ListenerEvent) -> bool) -> Option<ListenerEvent> {
+        if self.policy == CoalescePolicy::Newest {
+            let newest = self
+                .events
+                .iter()
+                .enumerate()
+                .filter(|(_, event)| relayable(event))
+                .max_by_key(|(_, event)| event.block_number())
+                .map(|(position, _)| position);
+            if let Some(newest) = newest {
+                for skipped in self.events.drain(..newest) {
+                    if relayable(&skipped) {
+                        tracing::info!("Skipping superseded root: {:?}", skipped);
+                        self.superseded.push(skipped);
+                    }
+                }
+            }
+        }
+
+        self.events.pop_front()
+    }
+
+    /// Whether a queued event supersedes the root of `block_number` taken
+    /// from the queue earlier, following the rule of [`Self::pop`]
+    pub fn supersedes(
+        &self,
+        block_number: u64,
+        relayable: impl Fn(&ListenerEvent) -> bool,
+    ) -> bool {
+        self.policy == CoalescePolicy::Newest
+            && self
+                .events
+                .iter()
+                .any(|event| relayable(event) && event.block_number() >= block_number)
+    }
+
+    /// Relayable events superseded since the last call
+    pub fn take_superseded(&mut self) -> Vec<ListenerEvent> {
+        std::mem::take(&mut self.superseded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::primitives::{B256, U256};
+
+    use super::*;
+    use crate::listener::{RootChange, TreeChangeKind};
+
+    fn change(block_number: u64, kind: TreeChangeKind) -> RootChange {
+        RootChange {
+            kind,
+            pre_root: U256::from(block_number),
+            post_root: U256::from(block_number + 1),
+            block_number,
+            block_hash: None,
+            tx_hash: B256::ZERO,
+            log_index: 0,
+        }
+    }
+
+    fn changed(block_number: u64, kind: TreeChangeKind) -> ListenerEvent {
+        ListenerEvent::Changed(change(block_number, kind))
+    }
+
+    /// Deletions are filtered out, everything else is relayed
+    fn relayable(event: &ListenerEvent) -> bool {
+        match event {
+            ListenerEvent::Changed(change) => change.kind != TreeChangeKind::Deletion,
+            ListenerEvent::Retracted(_) | ListenerEvent::RootObserved { .. } => true,
+            ListenerEvent::Finalized { .. } => false,
+        }
+    }
+
+    fn drain(queue: &mut RootQueue) -> Vec<ListenerEvent> {
+        std::iter::from_fn(|| queue.pop(relayable)).collect()
+    }
+
+    fn queue(policy: CoalescePolicy) -> RootQueue {
+        let mut queue = RootQueue::new(policy);
+        queue.push(changed(1, TreeChangeKind::Insertion));
+        queue.push(changed(2, TreeChangeKind::Update));
+        queue.push(changed(3, TreeChangeKind::Deletion));
+        queue.push(changed(4, TreeChangeKind::Insertion));
+        queue.push(changed(5, TreeChangeKind::Deletion));
+        queue
+    }
+
+    #[test]
+    fn newest_keeps_newest_relayable_and_later_events() {
+        let mut queue = queue(CoalescePolicy::Newest);
+
+        assert_eq!(
+            drain(&mut queue),
+            vec![
+                changed(4, TreeChangeKind::Insertion),
+                changed(5, TreeChangeKind::Deletion),
+            ]
+        );
+        assert!(queue.is_empty());
+        assert_eq!(
+            queue.take_superseded(),
+            vec![
+                changed(1, TreeChangeKind::Insertion),
+                changed(2, TreeChangeKind::Update),
+            ]
+        );
+        assert!(queue.take_superseded().is_empty());
+    }
+
+    #[test]
+    fn newest_without_relayable_event_pops_in_order() {
+        let mut queue = RootQueue::new(CoalescePolicy::Newest);
+        queue.push(changed(1, TreeChangeKind::Deletion));
+        queue.push(ListenerEvent::Finalized { block_number: 2 });
+
+        assert_eq!(
+            drain(&mut queue),
+            vec![
+                changed(1, TreeChangeKind::Deletion),
+                ListenerEvent::Finalized { block_number: 2 },
+            ]
+        );
+    }
+
+    #[test]
+    fn newest_keeps_lower_blocks_queued_after_highest() {
+        let mut queue = RootQueue::new(CoalescePolicy::Newest);
+        let retracted = ListenerEvent::Retracted(change(6, TreeChangeKind::Insertion));
+        queue.push(changed(4, TreeChangeKind::Insertion));
+        queue.push(retracted.clone());
+        queue.push(changed(5, TreeChangeKind::Update));
+
+        assert_eq!(
+            drain(&mut queue),
+            vec![retracted, changed(5, TreeChangeKind::Update)]
+        );
+        assert_eq!(
+            queue.take_superseded(),
+            vec![changed(4, TreeChangeKind::Insertion)]
+        );
+    }
+
+    #[test]
+    fn newer_relayable_event_supersedes_taken_root() {
+        let mut queue = RootQueue::new(CoalescePolicy::Newest);
+        queue.push(changed(3, TreeChangeKind::Insertion));
+        queue.push(changed(6, TreeChangeKind::Deletion));
+        assert!(!queue.supersedes(5, relayable));
+
+        queue.push(changed(5, TreeChangeKind::Update));
+        assert!(queue.supersedes(5, relayable));
+        assert!(!queue(CoalescePolicy::Every).supersedes(1, relayable));
+    }
+
+    #[test]
+    fn every_pops_all_events_in_order() {
+        let mut queue = queue(CoalescePolicy::Every);
+
+        assert_eq!(
+            drain(&mut queue),
+            vec![
+                changed(1, TreeChangeKind::Insertion),
+                changed(2, TreeChangeKind::Update),
+                changed(3, TreeChangeKind::Deletion),
+                changed(4, TreeChangeKind::Insertion),
+                changed(5, TreeChangeKind::Deletion),
+            ]
+        );
+        assert!(queue.take_superseded().is_empty());
+    }
+}
@@ -1,26 +1,70 @@
-use std::str::FromStr;
+use std::{future::Future, path::PathBuf, str::FromStr, time::Duration};
 
 use alloy::{
     eips::BlockId,
     primitives::{Address, FixedBytes, U256},
-    providers::{DynProvider, Provider, ProviderBuilder},
+    providers::Provider,
     rpc::types::BlockTransactionsKind,
 };
 use alloy_chains::Chain;
 use eyre::{Result, WrapErr};
-use futures_util::StreamExt;
+use futures_util::{future::join_all, StreamExt};
+use tokio::{
+    sync::{mpsc, watch},
+    task::{JoinError, JoinSet},
+    time::{timeout, timeout_at, Instant},
+};
 use types::{header::RlpHeader, proofs::AccountProof, ProverInput};
 
-use crate::{listener::WorldIDListener, prover::Risc0Prover, publisher::ProofPublisher, Config};
+use crate::{
+    artifacts::ArtifactStore,
+    backfill::{BackfillEntry, BackfillReport, BackfillStage},
+    checkpoint::{Checkpoint, CheckpointStore},
+    error::{InputError, RelayerError},
+    jobs::{Delivery, DeliveryState, JobRecord, JobState, JobStore, BLOCK_STATE},
+    listener::{is_overridden, ListenerEvent, RootChange, TreeChangeKind, WorldIDListener},
+    prover::{ExecutionReport, ProofArtifact, Prover},
+    provider::EthProvider,
+    publisher::{starknet_chain_id, Destination, DestinationConfig, ProofPublisher},
+    queue::{CoalescePolicy, RootQueue},
+    retry::{DeadLetter, DeadLetterStore, ErrorClass, Failure, RelayStage, RetryPolicy},
+    Config,
+};
+
+/// How the relay pipeline ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelayExit {
+    /// The listener stream ended and every stage completed
+    Completed,
+    /// Shutdown requested, in-flight work completed within the grace period
+    Drained,
+    /// Shutdown requested, in-flight work aborted after the grace period
+    Interrupted,
+}
+
+/// Number of proofs buffered between the prove and publish stages
+const PIPELINE_CAPACITY: usize = 1;
 
 #[derive(Debug, Clone)]
-pub struct Relayer {
+pub struct Relayer<P> {
     latest_root_slot: FixedBytes<32>,
     world_listener: WorldIDListener,
-    provider: DynProvider,
+    provider: EthProvider,
     world_id_addr: Address,
-    prover: Risc0Prover,
+    prover: P,
+    artifacts: ArtifactStore,
+    preflight: bool,
+    proving_timeout: Duration,
     proof_publisher: ProofPublisher,
+    checkpoints: CheckpointStore,
+    start_block: Option<u64>,
+    relay_kinds: Vec<TreeChangeKind>,
+    coalesce: CoalescePolicy,
+    shutdown_grace: Duration,
+    heartbeat: Option<Duration>,
+    retry: RetryPolicy,
+    dead_letters: DeadLetterStore,
+    jobs: JobStore,
     chain: Chain,
 }
 
@@ -35,26 +79,48 @@ impl RelayerBuilder {
         Self { config }
     }
 
-    pub async fn build(self) -> Result<Relayer> {
+    pub async fn build<P: Prover>(self, prover: P) -> Result<Relayer<P>> {
         let latest_root_slot =
             FixedBytes::<32>::from(U256::from(self.config.world_id_latest_root_slot));
-        let provider = ProviderBuilder::new()
-            .on_builtin(&self.config.ethereum_rpc_url)
-            .await?
-            .erased();
+        let provider =
+            EthProvider::connect(&self.config.ethereum_rpc_url, self.config.rpc_quorum).await?;
 
         let world_idm = Address::from_str(&self.config.world_id_manager)
             .wrap_err("Failed to parse World Identity Manager address")?;
-        let world_listener = WorldIDListener::new(provider.clone(), world_idm.clone());
+        // Push-based log subscriptions are only available over WebSocket. They
+        // come from a single endpoint, logs are queried in quorum mode so that
+        // a majority of endpoints agrees on them.
+        let pubsub = !provider.is_quorum()
+            && self
+                .config
+                .ethereum_rpc_url
+                .iter()
+                .all(|url| url.starts_with("ws"));
+        let mut world_listener = WorldIDListener::new(
+            provider.clone(),
+            world_idm,
+            self.config.confirmation_policy,
+            self.config.confirmations,
+            pubsub,
+            self.config.max_log_span,
+        );
+        if self.config.state_polling {
+            world_listener = world_listener
+                .with_state_polling(U256::from(self.config.world_id_latest_root_slot));
+        }
 
-        let prover = Risc0Prover {};
-        let publisher = ProofPublisher::new(
-            &self.config.starknet_rpc_url,
-            &self.config.starknet_private_key,
-            &self.config.starknet_account,
-            &self.config.relayer_verifier,
-            &self.config.chain,
-        )?;
+        let mut destinations = vec![DestinationConfig {
+            name: self.config.chain.to_string(),
+            rpc_url: self.config.starknet_rpc_url,
+            chain_id: starknet_chain_id(&self.config.chain)?.to_string(),
+            account: self.config.starknet_account,
+            private_key: self.config.starknet_private_key,
+            verifier: self.config.relayer_verifier,
+        }];
+        if let Some(path) = &self.config.destinations_file {
+            destinations.extend(DestinationConfig::load(path)?);
+        }
+        let publisher = ProofPublisher::new(&destinations)?;
 
         Ok(Relayer {
             latest_root_slot,
@@ -62,44 +128,994 @@ impl RelayerBuilder {
             provider,
             world_id_addr: world_idm,
             prover,
+            artifacts: ArtifactStore::new(&self.config.artifact_dir),
+            preflight: !self.config.skip_preflight,
+            proving_timeout: Duration::from_secs(self.config.proving_timeout),
             proof_publisher: publisher,
+            checkpoints: CheckpointStore::new(&self.config.state_file),
+            start_block: self.config.start_block,
+            relay_kinds: self.config.relay_kinds,
+            coalesce: self.config.coalesce,
+            shutdown_grace: Duration::from_secs(self.config.shutdown_grace),
+            heartbeat: self.config.heartbeat_interval.map(Duration::from_secs),
+            retry: RetryPolicy {
+                max_attempts: self.config.max_attempts.max(1),
+                base_delay: Duration::from_secs(self.config.retry_base_delay),
+                max_delay: Duration::from_secs(self.config.retry_max_delay),
+            },
+            dead_letters: DeadLetterStore::new(&self.config.dead_letter_file),
+            jobs: JobStore::new(&self.config.job_store),
             chain: self.config.chain,
         })
     }
 }
 
-impl Relayer {
-    pub async fn relay(&self) -> Result<()> {
-        let stream = self.world_listener.subscribe().await?;
-        tokio::pin!(stream);
+impl<P: Prover> Relayer<P> {
+    /// Relay root changes until the listener ends or `shutdown` resolves.
+    ///
+    /// On shutdown the listener stops, the root being proven and any proof
+    /// ready to be published are given the grace period to complete.
+    /// Anything left resumes from its last completed stage after a restart
+    /// since every job is written through to the job store.
+    pub async fn relay(&self, shutdown: impl Future<Output = ()>) -> Result<RelayExit> {
+        self.resume_jobs().await?;
+
+        // An explicit start block overrides the stored checkpoint
+        let resume = match self.start_block {
+            Some(block) => Some(Checkpoint::before_block(block)),
+            None => self.checkpoints.load()?,
+        };
+
+        let stream = self.world_listener.subscribe(resume).await?;
+
+        // Listener, input fetching, proving and publishing run as separate
+        // tasks. Bounded channels between the last stages provide backpressure
+        // while the unbounded listener channel lets the queue coalesce roots.
+        // The input of the next root is fetched while the previous one is
+        // proven and only handed over once the prover asks for it, so that
+        // newer roots keep superseding it until then.
+        let (event_sender, event_receiver) = mpsc::unbounded_channel();
+        let (demand_sender, demand_receiver) = mpsc::channel(1);
+        let (input_sender, input_receiver) = mpsc::channel(1);
+        let (proof_sender, proof_receiver) = mpsc::channel(PIPELINE_CAPACITY);
+
+        let (stop_sender, stop) = watch::channel(false);
+        // Time of the last confirmed relay, drives the heartbeat
+        let (relayed_sender, relayed) = watch::channel(Instant::now());
+
+        let mut stages = JoinSet::new();
+        let listener = stages.spawn(async move {
+            tokio::pin!(stream);
+            while let Some(event) = stream.next().await {
+                if event_sender.send(event).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        });
+        let fetch = stages.spawn(self.clone().fetch_stage(
+            event_receiver,
+            demand_receiver,
+            input_sender,
+            relayed,
+        ));
+        stages.spawn(
+            self.clone()
+                .prove_stage(demand_sender, input_receiver, proof_sender, stop),
+        );
+        stages.spawn(
+            self.clone()
+                .publish_stage(proof_receiver, resume, relayed_sender),
+        );
+
+        // The first stage to fail stops the whole pipeline
+        tokio::pin!(shutdown);
+        loop {
+            tokio::select! {
+                result = stages.join_next() => match result {
+                    Some(result) => {
+                        if let Err(e) = stage_result(result) {
+                            stages.abort_all();
+                            return Err(e);
+                        }
+                    }
+                    None => return Ok(RelayExit::Completed),
+                },
+                _ = &mut shutdown => break,
+            }
+        }
+
+        tracing::info!(
+            "Shutting down, waiting up to {:?} for in-flight work",
+            self.shutdown_grace
+        );
+        listener.abort();
+        fetch.abort();
+        // Stop the prover from starting new jobs, the channels then drain
+        let _ = stop_sender.send(true);
+
+        let drain = async {
+            while let Some(result) = stages.join_next().await {
+                stage_result(result)?;
+            }
+            Ok::<_, eyre::Report>(())
+        };
+        let drained = timeout(self.shutdown_grace, drain).await;
+        let exit = match drained {
+            Ok(result) => result.map(|_| RelayExit::Drained)?,
+            Err(_) => {
+                stages.abort_all();
+                tracing::warn!(
+                    "Grace period elapsed, in-flight roots resume from their last stage on restart"
+                );
+                RelayExit::Interrupted
+            }
+        };
+        self.jobs.flush().await?;
+
+        Ok(exit)
+    }
+
+    /// Complete the jobs a previous run left unfinished, in block order, from
+    /// their last completed stage
+    async fn resume_jobs(&self) -> Result<()> {
+        let mut checkpoint = self.checkpoints.load()?;
+        for mut record in self.jobs.unfinished()? {
+            tracing::info!(
+                "Resuming block {} from {:?}",
+                record.block_number,
+                record.state
+            );
+            self.fetch_step(&mut record).await?;
+            self.prove_step(&mut record).await?;
+            self.publish_step(&mut record).await?;
+            // Replayed dead letters come before the stored checkpoint
+            if let Some(reached) = record.checkpoint {
+                if checkpoint.is_none_or(|checkpoint| reached > checkpoint) {
+                    self.checkpoints.save(&reached)?;
+                    checkpoint = Some(reached);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Coalesce listener events and prepare the prover input of the next
+    /// root while the previous one is proven, handing it over once the prove
+    /// stage asks for it
+    async fn fetch_stage(
+        self,
+        mut events: mpsc::UnboundedReceiver<ListenerEvent>,
+        mut demand: mpsc::Receiver<()>,
+        inputs: mpsc::Sender<JobRecord>,
+        relayed: watch::Receiver<Instant>,
+    ) -> Result<()> {
+        let mut queue = RootQueue::new(self.coalesce);
+        let mut last_heartbeat = Instant::now();
+        let mut prefetched = None;
+        loop {
+            if queue.is_empty() && prefetched.is_none() {
+                match self
+                    .next_event(&mut events, &relayed, &mut last_heartbeat)
+                    .await
+                {
+                    Some(event) => queue.push(event),
+                    None => break,
+                }
+            }
+            while let Ok(event) = events.try_recv() {
+                queue.push(event);
+            }
+
+            if prefetched.is_none() {
+                let next = queue.pop(|event| self.is_relayable(event));
+                for event in queue.take_superseded() {
+                    self.record_superseded(event)?;
+                }
+                if let Some(event) = next {
+                    prefetched = self.fetch_input(event).await?;
+                }
+            }
+            let Some(mut job) = prefetched.take() else {
+                continue;
+            };
+
+            if demand.recv().await.is_none() {
+                break;
+            }
+            // Roots queued while the prover was busy may supersede this one
+            while let Ok(event) = events.try_recv() {
+                queue.push(event);
+            }
+            if !job.state.is_finished()
+                && queue.supersedes(job.block_number, |event| self.is_relayable(event))
+            {
+                tracing::info!(
+                    "Block {} superseded while waiting for the prover",
+                    job.block_number
+                );
+                self.advance(&mut job, JobState::Superseded)?;
+            }
+
+            if inputs.send(job).await.is_err() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Wait for the next listener event. In heartbeat mode, the root at the
+    /// newest finalized block is observed once no root was confirmed on
+    /// Starknet, nor observed, for the heartbeat interval.
+    async fn next_event(
+        &self,
+        events: &mut mpsc::UnboundedReceiver<ListenerEvent>,
+        relayed: &watch::Receiver<Instant>,
+        last_heartbeat: &mut Instant,
+    ) -> Option<ListenerEvent> {
+        let Some(interval) = self.heartbeat else {
+            return events.recv().await;
+        };
+
+        loop {
+            let last = (*relayed.borrow()).max(*last_heartbeat);
+            if let Ok(event) = timeout_at(last + interval, events.recv()).await {
+                return event;
+            }
+
+            *last_heartbeat = Instant::now();
+            match self.world_listener.observe_root().await {
+                Ok(event) => {
+                    tracing::info!("No root relayed for {interval:?}, relaying the current root");
+                    return Some(event);
+                }
+                Err(e) => tracing::warn!("Failed to observe the current root: {}", e),
+            }
+        }
+    }
+
+    /// Turn a listener event into a job, fetching the prover input if the
+    /// root must be relayed
+    async fn fetch_input(&self, event: ListenerEvent) -> Result<Option<JobRecord>> {
+        let (block_number, log_index, root, checkpoint) = match event {
+            ListenerEvent::Changed(change) => {
+                tracing::info!(
+                    "New root detected: {:?} ({:?})",
+                    change.post_root,
+                    change.kind
+                );
+                let checkpoint = Some(Checkpoint::from(&change));
+
+                if !self.should_relay(&change) {
+                    tracing::info!("Skipping {:?} root change", change.kind);
+                    return Ok(Some(JobRecord::skipped(change.block_number, checkpoint)));
+                }
+                if self.is_overridden(&change).await {
+                    tracing::info!(
+                        "Root {:?} replaced later in block {}, skipping",
+                        change.post_root,
+                        change.block_number
+                    );
+                    let mut record = JobRecord::superseded(
+                        change.block_number,
+                        change.log_index,
+                        change.post_root,
+                    );
+                    record.checkpoint = checkpoint;
+                    self.jobs.put(&mut record)?;
+                    return Ok(Some(record));
+                }
+                (
+                    change.block_number,
+                    change.log_index,
+                    Some(change.post_root),
+                    checkpoint,
+                )
+            }
+            ListenerEvent::Retracted(change) => {
+                tracing::warn!(
+                    "Root {:?} at block {} retracted by a reorg",
+                    change.post_root,
+                    change.block_number
+                );
+                if !self.holds_root(change.block_number, change.post_root).await {
+                    return Ok(None);
+                }
+
+                // The store does not go back to lower blocks, the canonical
+                // root of the same block replaces the orphaned one
+                tracing::warn!(
+                    "Starknet holds the retracted root, relaying the canonical root of block {}",
+                    change.block_number
+                );
+                let mut record = JobRecord::new(change.block_number, BLOCK_STATE, None, None);
+                self.jobs.put(&mut record)?;
+                self.fetch_step(&mut record).await?;
+                return Ok(Some(record));
+            }
+            ListenerEvent::RootObserved { root, block_number } => {
+                tracing::info!("Relaying root {:?} observed at block {block_number}", root);
+                (block_number, BLOCK_STATE, Some(root), None)
+            }
+            ListenerEvent::Finalized { block_number } => {
+                let checkpoint = Checkpoint::before_block(block_number + 1);
+                return Ok(Some(JobRecord::skipped(block_number, Some(checkpoint))));
+            }
+        };
+
+        if let Some(record) = self.jobs.get(block_number, log_index)? {
+            // A job of the same position on an orphaned fork relayed another root
+            if record.state == JobState::Confirmed && record.root == root {
+                tracing::info!("Block {block_number} already relayed");
+                return Ok(Some(JobRecord::skipped(block_number, checkpoint)));
+            }
+        }
+
+        if self.is_superseded(block_number, root).await {
+            return Ok(Some(JobRecord::skipped(block_number, checkpoint)));
+        }
+
+        let mut record = JobRecord::new(block_number, log_index, root, checkpoint);
+        self.jobs.put(&mut record)?;
+        self.fetch_step(&mut record).await?;
+
+        Ok(Some(record))
+    }
+
+    /// Keep track of a root dropped by the queue in favor of a newer one
+    fn record_superseded(&self, event: ListenerEvent) -> Result<()> {
+        let mut record = match event {
+            ListenerEvent::Changed(change) => {
+                JobRecord::superseded(change.block_number, change.log_index, change.post_root)
+            }
+            ListenerEvent::RootObserved { root, block_number } => {
+                JobRecord::superseded(block_number, BLOCK_STATE, root)
+            }
+            ListenerEvent::Retracted(_) | ListenerEvent::Finalized { .. } => return Ok(()),
+        };
+
+        // A root relayed by a previous run keeps its history
+        if self
+            .jobs
+            .get(record.block_number, record.log_index)?
+            .is_some()
+        {
+            return Ok(());
+        }
+        self.jobs.put(&mut record)
+    }
+
+    /// Generate the proof of each prepared input, asking for the next one
+    /// once the previous proof is handed over
+    async fn prove_stage(
+        self,
+        demand: mpsc::Sender<()>,
+        mut inputs: mpsc::Receiver<JobRecord>,
+        proofs: mpsc::Sender<JobRecord>,
+        stop: watch::Receiver<bool>,
+    ) -> Result<()> {
+        loop {
+            if demand.send(()).await.is_err() {
+                break;
+            }
+            let Some(mut record) = inputs.recv().await else {
+                break;
+            };
+            if *stop.borrow() {
+                tracing::info!("Dropping block {} on shutdown", record.block_number);
+                break;
+            }
+
+            self.prove_step(&mut record).await?;
+            if proofs.send(record).await.is_err() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Publish each proof to Starknet and record the listener progress.
+    ///
+    /// The checkpoint only moves forward, the finalized head may lag behind
+    /// root changes of unfinalized blocks.
+    async fn publish_stage(
+        self,
+        mut proofs: mpsc::Receiver<JobRecord>,
+        mut checkpoint: Option<Checkpoint>,
+        relayed: watch::Sender<Instant>,
+    ) -> Result<()> {
+        while let Some(mut record) = proofs.recv().await {
+            self.publish_step(&mut record).await?;
+            if record.state == JobState::Confirmed {
+                relayed.send_replace(Instant::now());
+            }
+            if let Some(reached) = record.checkpoint {
+                if checkpoint.is_none_or(|checkpoint| reached > checkpoint) {
+                    self.checkpoints.save(&reached)?;
+                    checkpoint = Some(reached);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Fetch the prover input of a detected job
+    async fn fetch_step(&self, record: &mut JobRecord) -> Result<()> {
+        if record.state != JobState::Detected {
+            return Ok(());
+        }
+
+        let (block_number, root) = (record.block_number, record.root);
+        let input = self
+            .retry
+            .run("Fetching prover input", || async move {
+                let input = self.prepare_prover_input(block_number).await?;
+                self.check_root(&input, root).await?;
+                Ok(input)
+            })
+            .await;
+        match input {
+            Ok(input) => {
+                record.root = Some(input.account_proof.storage_proof.value);
+                record.input = Some(input);
+                self.advance(record, JobState::InputFetched)
+            }
+            Err(failure) => self.fail(record, RelayStage::Fetch, &failure),
+        }
+    }
+
+    /// Prove the fetched input of a job
+    async fn prove_step(&self, record: &mut JobRecord) -> Result<()> {
+        if record.state != JobState::InputFetched {
+            return Ok(());
+        }
+        // Jobs published while this one was waiting may have superseded it
+        if self.is_superseded(record.block_number, record.root).await {
+            return self.advance(record, JobState::Superseded);
+        }
+        let input = record
+            .input
+            .clone()
+            .ok_or_else(|| eyre::eyre!("Job {} has no prover input", record.block_number))?;
+
+        tracing::info!("Proving block {}", record.block_number);
+        let proof = self
+            .retry
+            .run("Proving", || self.prove(input.clone()))
+            .await;
+        match proof {
+            Ok((path, _)) => {
+                record.proof = Some(path);
+                self.advance(record, JobState::Proved)
+            }
+            Err(failure) => self.fail(record, RelayStage::Prove, &failure),
+        }
+    }
+
+    /// Publish the proof of a job to every destination, then wait for the
+    /// transactions to be accepted. Destinations are retried independently
+    /// and the ones already delivered are left alone on resume.
+    async fn publish_step(&self, record: &mut JobRecord) -> Result<()> {
+        if !matches!(record.state, JobState::Proved | JobState::Submitted) {
+            return Ok(());
+        }
+        let Some(proof) = self.read_proof(record).await? else {
+            return Ok(());
+        };
+        let destinations = self.proof_publisher.destinations();
+
+        let submitted = join_all(destinations.iter().map(|destination| {
+            let delivery = record.delivery(destination.name());
+            self.submit(destination, &proof, record.block_number, delivery)
+        }))
+        .await;
+        let mut failure = record_deliveries(record, destinations, submitted);
+        self.advance(record, JobState::Submitted)?;
+
+        let confirmed = join_all(destinations.iter().map(|destination| {
+            let delivery = record.delivery(destination.name());
+            self.confirm(destination, delivery)
+        }))
+        .await;
+        failure = failure.or(record_deliveries(record, destinations, confirmed));
+
+        match failure {
+            Some(failure) => self.fail(record, RelayStage::Publish, &failure),
+            None => self.advance(record, JobState::Confirmed),
+        }
+    }
+
+    /// Read the proof of a job. An artifact that cannot be read any more is
+    /// proven again from the job input, the job fails without one. Returns
+    /// `None` once the job failed or was superseded.
+    async fn read_proof(&self, record: &mut JobRecord) -> Result<Option<ProofArtifact>> {
+        let mut proven_again = false;
+        loop {
+            let read = match &record.proof {
+                Some(path) => self.artifacts.read(path),
+                None => Err(eyre::eyre!("Job {} has no proof", record.block_number)),
+            };
+            let error = match read {
+                Ok(proof) => return Ok(Some(proof)),
+                Err(e) => e,
+            };
+
+            if proven_again || record.input.is_none() {
+                let failure = Failure {
+                    error,
+                    class: ErrorClass::Permanent,
+                    attempts: 1,
+                };
+                self.fail(record, RelayStage::Publish, &failure)?;
+                return Ok(None);
+            }
+
+            tracing::warn!("{:#}, proving block {} again", error, record.block_number);
+            record.proof = None;
+            self.advance(record, JobState::InputFetched)?;
+            self.prove_step(record).await?;
+            if record.state != JobState::Proved {
+                return Ok(None);
+            }
+            proven_again = true;
+        }
+    }
+
+    /// Send a pending delivery, skipping destinations already holding a
+    /// newer root
+    async fn submit(
+        &self,
+        destination: &Destination,
+        proof: &ProofArtifact,
+        block_number: u64,
+        mut delivery: Delivery,
+    ) -> (Delivery, Option<Failure>) {
+        if delivery.state != DeliveryState::Pending {
+            return (delivery, None);
+        }
+
+        if let Ok(stored) = destination.latest_root_block().await {
+            if stored.covers(block_number, Some(proof.root)) {
+                tracing::info!(
+                    "{} already holds block {}, skipping block {block_number}",
+                    destination.name(),
+                    stored.block_number
+                );
+                delivery.state = DeliveryState::Skipped;
+                return (delivery, None);
+            }
+        }
+
+        let operation = format!("Publishing to {}", destination.name());
+        match self
+            .retry
+            .run(&operation, || destination.publish(proof))
+            .await
+        {
+            Ok(tx_hash) => {
+                delivery.state = DeliveryState::Submitted;
+                delivery.tx_hash = Some(tx_hash);
+                (delivery, None)
+            }
+            Err(failure) => failed_delivery(destination, delivery, failure),
+        }
+    }
+
+    /// Wait for the transaction of a submitted delivery
+    async fn confirm(
+        &self,
+        destination: &Destination,
+        mut delivery: Delivery,
+    ) -> (Delivery, Option<Failure>) {
+        let (DeliveryState::Submitted, Some(tx_hash)) = (delivery.state, delivery.tx_hash) else {
+            return (delivery, None);
+        };
+
+        let operation = format!("Confirming on {}", destination.name());
+        let confirmed = self
+            .retry
+            .run(&operation, || destination.wait_for_confirmation(tx_hash))
+            .await;
+        match confirmed {
+            Ok(()) => {
+                delivery.state = DeliveryState::Confirmed;
+                (delivery, None)
+            }
+            Err(failure) => failed_delivery(destination, delivery, failure),
+        }
+    }
+
+    /// Move a job to its next state and write it through
+    fn advance(&self, record: &mut JobRecord, state: JobState) -> Result<()> {
+        record.state = state;
+        self.jobs.put(record)
+    }
+
+    /// Mark a job as failed and dead-letter its root
+    fn fail(&self, record: &mut JobRecord, stage: RelayStage, failure: &Failure) -> Result<()> {
+        self.advance(record, JobState::Failed)?;
+        self.dead_letter(record, stage, failure)
+    }
+
+    /// Record a root that could not be relayed. The job still moves the
+    /// checkpoint forward, the root is retried through [`Self::replay`].
+    fn dead_letter(&self, record: &JobRecord, stage: RelayStage, failure: &Failure) -> Result<()> {
+        let letter = DeadLetter::new(record, stage, failure);
+        tracing::error!("Dead letter: {letter}");
+        self.dead_letters.push(letter)
+    }
+
+    /// Roots that could not be relayed
+    pub fn dead_letters(&self) -> Result<Vec<DeadLetter>> {
+        self.dead_letters.list()
+    }
+
+    /// Relay the dead-lettered roots again, or only the ones of `block` if
+    /// given. Returns the number of roots relayed and still failing.
+    pub async fn replay(&self, block: Option<u64>) -> Result<(usize, usize)> {
+        let letters = self.dead_letters.list()?;
+        let (mut relayed, mut failed) = (0, 0);
+
+        for letter in letters {
+            if block.is_some_and(|block| block != letter.block_number) {
+                continue;
+            }
+            let (block_number, log_index) = (letter.block_number, letter.log_index);
+
+            // A newer root on Starknet makes the dead letter obsolete
+            if let Ok(stored) = self.proof_publisher.latest_root_block().await {
+                if stored.covers(block_number, letter.root) {
+                    tracing::info!(
+                        "Block {block_number} superseded by block {}",
+                        stored.block_number
+                    );
+                    self.dead_letters.remove(block_number, log_index)?;
+                    continue;
+                }
+            }
+
+            tracing::info!("Replaying block {block_number} log {log_index}");
+            // The job keeps its checkpoint for a new dead letter, resuming it
+            // never moves the stored checkpoint backwards
+            let mut record = match self.jobs.get(block_number, log_index)? {
+                Some(mut record) => {
+                    record.reopen();
+                    record
+                }
+                None => JobRecord::new(block_number, log_index, letter.root, letter.checkpoint),
+            };
+            self.jobs.put(&mut record)?;
+            self.fetch_step(&mut record).await?;
+            self.prove_step(&mut record).await?;
+            self.publish_step(&mut record).await?;
+
+            if record.state == JobState::Confirmed {
+                self.dead_letters.remove(block_number, log_index)?;
+                relayed += 1;
+            } else {
+                failed += 1;
+            }
+        }
+
+        Ok((relayed, failed))
+    }
+
+    /// Re-run the relay pipeline over an explicit block range.
+    ///
+    /// Every root change gets its prover input prepared, then optionally
+    /// proven and published. Failures are recorded in the report and do not
+    /// stop the remaining root changes.
+    pub async fn backfill(
+        &self,
+        from_block: u64,
+        to_block: u64,
+        prove: bool,
+        publish: bool,
+    ) -> Result<BackfillReport> {
+        let mut report = BackfillReport::new(from_block, to_block);
+
+        let changes = self
+            .world_listener
+            .root_changes(from_block, to_block)
+            .await?;
+        let relayed: Vec<_> = changes
+            .iter()
+            .filter(|change| self.should_relay(change))
+            .cloned()
+            .collect();
+        tracing::info!(
+            "Found {} root changes between blocks {from_block} and {to_block}",
+            relayed.len()
+        );
+
+        for change in relayed {
+            // Changes of filtered kinds replace the root of the block as well
+            if is_overridden(&change, &changes) {
+                report.entries.push(BackfillEntry {
+                    change,
+                    stage: BackfillStage::Superseded,
+                    error: None,
+                });
+                continue;
+            }
+
+            let mut entry = BackfillEntry {
+                change,
+                stage: BackfillStage::Detected,
+                error: None,
+            };
+            if let Err(e) = self.backfill_root(&mut entry, prove, publish).await {
+                tracing::error!(
+                    "Backfill of block {} failed: {e}",
+                    entry.change.block_number
+                );
+                entry.error = Some(e.to_string());
+            }
+            report.entries.push(entry);
+        }
+
+        Ok(report)
+    }
+
+    /// Execute the guest on the input of a block without proving it, with
+    /// the selected prover backend
+    pub async fn execute(&self, block_number: u64) -> Result<ExecutionReport> {
+        let input = self.prepare_prover_input(block_number).await?;
+        self.check_root(&input, None).await?;
+
+        self.prover.execute(input).await
+    }
+
+    /// Whether every Starknet destination already holds the root of this
+    /// block or a newer one. The store does not go back to lower blocks,
+    /// proving would waste prover time.
+    async fn is_superseded(&self, block_number: u64, root: Option<U256>) -> bool {
+        match self.proof_publisher.latest_root_block().await {
+            Ok(stored) if stored.covers(block_number, root) => {
+                tracing::info!(
+                    "Starknet already holds root {:#x} from block {}, skipping block {block_number}",
+                    stored.root,
+                    stored.block_number
+                );
+                true
+            }
+            Ok(_) => false,
+            Err(e) => {
+                tracing::warn!("Failed to read the Starknet store: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Whether a Starknet destination holds this root of this block
+    async fn holds_root(&self, block_number: u64, root: U256) -> bool {
+        let destinations = self.proof_publisher.destinations();
+        let stored = join_all(
+            destinations
+                .iter()
+                .map(|destination| destination.latest_root_block()),
+        )
+        .await;
 
-        while let Some((event, block_number)) = stream.next().await {
-            let new_root = event.postRoot;
-            tracing::info!("New root detected: {:?}", new_root);
+        destinations
+            .iter()
+            .zip(stored)
+            .any(|(destination, stored)| match stored {
+                Ok(stored) => stored.block_number == block_number && stored.root == root,
+                Err(e) => {
+                    tracing::warn!("Failed to read the {} store: {}", destination.name(), e);
+                    false
+                }
+            })
+    }
+
+    /// Whether a later change of the same block replaced the root, which
+    /// then differs from the one of the block state
+    async fn is_overridden(&self, change: &RootChange) -> bool {
+        let block_number = change.block_number;
+        match self
+            .world_listener
+            .root_changes(block_number, block_number)
+            .await
+        {
+            Ok(changes) => is_overridden(change, &changes),
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to read the root changes of block {block_number}: {}",
+                    e
+                );
+                false
+            }
+        }
+    }
+
+    /// Whether the kind of the root change was selected for relaying
+    fn should_relay(&self, change: &RootChange) -> bool {
+        self.relay_kinds.contains(&change.kind)
+    }
+
+    /// Whether the event may lead to a new root being proven. A retracted
+    /// root already relayed is replaced by the canonical root of its block.
+    fn is_relayable(&self, event: &ListenerEvent) -> bool {
+        match event {
+            ListenerEvent::Changed(change) => self.should_relay(change),
+            ListenerEvent::Retracted(_) | ListenerEvent::RootObserved { .. } => true,
+            ListenerEvent::Finalized { .. } => false,
+        }
+    }
+
+    async fn backfill_root(
+        &self,
+        entry: &mut BackfillEntry,
+        prove: bool,
+        publish: bool,
+    ) -> Result<()> {
+        let prover_input = self.prepare_prover_input(entry.change.block_number).await?;
+        self.check_root(&prover_input, Some(entry.change.post_root))
+            .await?;
+        entry.stage = BackfillStage::InputPrepared;
+        if !prove {
+            return Ok(());
+        }
+
+        let (_, proof) = self.prove(prover_input).await?;
+        entry.stage = BackfillStage::Proven;
+        if !publish {
+            return Ok(());
+        }
+
+        // Same delivery path as the relay, without the job store
+        let (block_number, proof) = (entry.change.block_number, &proof);
+        let deliveries = join_all(self.proof_publisher.destinations().iter().map(
+            |destination| async move {
+                let submitted = self
+                    .submit(destination, proof, block_number, Delivery::default())
+                    .await;
+                match submitted {
+                    (delivery, None) => self.confirm(destination, delivery).await,
+                    failed => failed,
+                }
+            },
+        ))
+        .await;
+        let skipped = deliveries
+            .iter()
+            .all(|(delivery, _)| delivery.state == DeliveryState::Skipped);
+        if let Some(failure) = deliveries.into_iter().find_map(|(_, failure)| failure) {
+            return Err(failure.error);
+        }
+        entry.stage = if skipped {
+            BackfillStage::Skipped
+        } else {
+            BackfillStage::Published
+        };
+
+        Ok(())
+    }
+
+    /// Prove the input, reusing the artifact of a previous attempt if any.
+    /// Returns the artifact along with the path it is saved at.
+    async fn prove(&self, input: ProverInput) -> Result<(PathBuf, ProofArtifact)> {
+        let kind = self.prover.kind();
+        let (block_number, root) = (input.header.number, input.account_proof.storage_proof.value);
+        if let Some((path, artifact)) = self.artifacts.load(kind, &input)? {
+            match artifact.verify(block_number, root) {
+                Ok(()) => {
+                    tracing::info!("Reusing the proof of block {block_number}");
+                    return Ok((path, artifact));
+                }
+                Err(e) => tracing::warn!("Ignoring the proof at {}: {:#}", path.display(), e),
+            }
+        }
 
-            let prover_input = self.prepare_prover_input(block_number).await?;
-            let proof = self.prover.prove(prover_input).await?;
-            self.proof_publisher.publish(&proof).await?;
+        // The preflight counts against the proving deadline. Dropping the
+        // proving future on timeout abandons the job, resumed by the next
+        // attempt or cancelled depending on the backend.
+        let artifact = timeout(self.proving_timeout, async {
+            if self.preflight && self.prover.needs_preflight() {
+                let report = self.prover.execute(input.clone()).await?;
+                tracing::info!(
+                    "Preflight of block {}: {} segments, {} cycles",
+                    report.block_number,
+                    report.segments,
+                    report.total_cycles
+                );
+            }
+            self.prover.prove(input.clone()).await
+        })
+        .await
+        .map_err(|_| RelayerError::Timeout(self.proving_timeout))??;
+        let path = self.artifacts.save(kind, &input, &artifact)?;
+        tracing::info!(
+            "Saved the proof of block {} to {}",
+            artifact.block_number,
+            path.display()
+        );
+
+        Ok((path, artifact))
+    }
+
+    /// Make sure the storage value proven by the input is the World ID root
+    /// of its block, and the expected root if given
+    async fn check_root(&self, input: &ProverInput, expected: Option<U256>) -> Result<()> {
+        let block_number = input.header.number;
+        let proven = input.account_proof.storage_proof.value;
+
+        if let Some(expected) = expected {
+            if expected != proven {
+                return Err(RelayerError::from(InputError::RootMismatch {
+                    block_number,
+                    expected_from: "postRoot",
+                    expected,
+                    proven,
+                })
+                .into());
+            }
+        }
+
+        let latest = self.world_listener.latest_root(block_number).await?;
+        if latest != proven {
+            return Err(RelayerError::from(InputError::RootMismatch {
+                block_number,
+                expected_from: "latestRoot()",
+                expected: latest,
+                proven,
+            })
+            .into());
         }
 
         Ok(())
     }
 
     async fn prepare_prover_input(&self, block_number: u64) -> Result<ProverInput> {
+        let block_id = BlockId::from(block_number);
+
         // Make the calls for proving
         let block = self
             .provider
-            .get_block(BlockId::from(block_number), BlockTransactionsKind::Hashes)
-            .await?
-            .unwrap();
+            .failover(|provider| async move {
+                provider
+                    .get_block(block_id, BlockTransactionsKind::Hashes)
+                    .await?
+                    .ok_or_else(|| eyre::eyre!("Block {block_number} not found"))
+            })
+            .await?;
 
         let block_hash = block.header.hash;
         let block = block.into_consensus();
 
+        // The header must be the one the endpoints agree upon
+        if self.provider.is_quorum() {
+            let agreed_hash = self
+                .provider
+                .consensus(|provider| async move {
+                    let block = provider
+                        .get_block(block_id, BlockTransactionsKind::Hashes)
+                        .await?
+                        .ok_or_else(|| eyre::eyre!("Block {block_number} not found"))?;
+                    Ok(block.header.hash)
+                })
+                .await?;
+            if agreed_hash != block_hash {
+                return Err(eyre::eyre!(
+                    "Block {block_number} hash {block_hash} differs from quorum hash {agreed_hash}"
+                ));
+            }
+        }
+
+        let (world_id_addr, latest_root_slot) = (self.world_id_addr, self.latest_root_slot);
         let account_proof = self
             .provider
-            .get_proof(self.world_id_addr, vec![self.latest_root_slot])
-            .block_id(BlockId::from(block_number))
+            .failover(|provider| async move {
+                Ok(provider
+                    .get_proof(world_id_addr, vec![latest_root_slot])
+                    .block_id(block_id)
+                    .await?)
+            })
             .await?;
 
         Ok(ProverInput {
@@ -109,3 +1125,44 @@ impl Relayer {
         })
     }
 }
+
+/// Store the deliveries of a job, returning the first failure
+fn record_deliveries(
+    record: &mut JobRecord,
+    destinations: &[Destination],
+    results: Vec<(Delivery, Option<Failure>)>,
+) -> Option<Failure> {
+    let mut failure = None;
+    for (destination, (delivery, failed)) in destinations.iter().zip(results) {
+        record
+            .deliveries
+            .insert(destination.name().to_string(), delivery);
+        failure = failure.or(failed);
+    }
+
+    failure
+}
+
+/// Mark a delivery as failed, naming its destination in the error
+fn failed_delivery(
+    destination: &Destination,
+    mut delivery: Delivery,
+    mut failure: Failure,
+) -> (Delivery, Option<Failure>) {
+    failure.error = failure
+        .error
+        .wrap_err(format!("Delivery to {} failed", destination.name()));
+    delivery.state = DeliveryState::Failed;
+    delivery.error = Some(format!("{:#}", failure.error));
+
+    (delivery, Some(failure))
+}
+
+/// Outcome of a pipeline stage, aborted stages are not an error
+fn stage_result(result: Result<Result<()>, JoinError>) -> Result<()> {
+    match result {
+        Ok(stage) => stage,
+        Err(e) if e.is_cancelled() => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,362 @@
+use std::{
+    fmt, fs,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use alloy::primitives::U256;
+use eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use tokio::{task::JoinError, time::sleep};
+
+use crate::{
+    checkpoint::Checkpoint,
+    error::RelayerError,
+    jobs::{JobRecord, BLOCK_STATE},
+    util::{unix_timestamp, write_atomic},
+};
+
+/// Whether a failed operation is worth retrying
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ErrorClass {
+    /// Network hiccups, rate limits, timeouts...
+    Transient,
+    /// Invalid proofs, rejected transactions...
+    Permanent,
+}
+
+impl ErrorClass {
+    pub fn of(error: &eyre::Report) -> Self {
+        if error
+            .downcast_ref::<RelayerError>()
+            .is_some_and(|e| e.is_permanent())
+        {
+            return Self::Permanent;
+        }
+        // A panicking prover fails the same way on every attempt
+        if error
+            .downcast_ref::<JoinError>()
+            .is_some_and(|e| e.is_panic())
+        {
+            return Self::Permanent;
+        }
+
+        Self::Transient
+    }
+}
+
+/// Pipeline stage a root failed at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RelayStage {
+    Fetch,
+    Prove,
+    Publish,
+}
+
+impl fmt::Display for RelayStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fetch => write!(f, "fetch"),
+            Self::Prove => write!(f, "prove"),
+            Self::Publish => write!(f, "publish"),
+        }
+    }
+}
+
+/// Error of an operation that exhausted its retries
+#[derive(Debug)]
+pub struct Failure {
+    pub error: eyre::Report,
+    pub class: ErrorClass,
+    pub attempts: u32,
+}
+
+/// Exponential backoff retry policy
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Delay before the attempt following `attempt`
+    fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    /// Run the operation until it succeeds, fails permanently or reaches the
+    /// maximum number of attempts.
+    pub async fn run<T, F, Fut>(&self, operation: &str, mut f: F) -> Result<T, Failure>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let error = match f().await {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+
+            let class = ErrorClass::of(&error);
+            if class == ErrorClass::Permanent || attempt >= self.max_attempts {
+                tracing::error!(
+                    "{operation} failed after {attempt} attempts ({class:?}): {}",
+                    error
+                );
+                return Err(Failure {
+                    error,
+                    class,
+                    attempts: attempt,
+                });
+            }
+
+            let delay = self.delay(attempt);
+            tracing::warn!(
+                "{operation} failed (attempt {attempt}/{}), retrying in {delay:?}: {}",
+                self.max_attempts,
+                error
+            );
+            sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+/// Root that could not be relayed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub block_number: u64,
+    /// Log index of the job, [`BLOCK_STATE`] for roots read from the state
+    pub log_index: u64,
+    /// Root the proof must attest to, if known
+    pub root: Option<U256>,
+    /// Listener position of the root, `None` for roots found by state polling
+    pub checkpoint: Option<Checkpoint>,
+    pub stage: RelayStage,
+    pub class: ErrorClass,
+    pub attempts: u32,
+    pub error: String,
+    /// Unix timestamp of the last failure
+    pub failed_at: u64,
+}
+
+impl DeadLetter {
+    pub fn new(record: &JobRecord, stage: RelayStage, failure: &Failure) -> Self {
+        Self {
+            block_number: record.block_number,
+            log_index: record.log_index,
+            root: record.root,
+            checkpoint: record.checkpoint,
+            stage,
+            class: failure.class,
+            attempts: failure.attempts,
+            error: format!("{:#}", failure.error),
+            failed_at: unix_timestamp(),
+        }
+    }
+}
+
+impl fmt::Display for DeadLetter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "block {}", self.block_number)?;
+        if self.log_index != BLOCK_STATE {
+            write!(f, " log {}", self.log_index)?;
+        }
+        write!(
+            f,
+            " failed at {} after {} attempts ({:?}): {}",
+            self.stage, self.attempts, self.class, self.error
+        )
+    }
+}
+
+/// Dead-letter list persisted as a JSON file
+#[derive(Debug, Clone)]
+pub struct DeadLetterStore {
+    path: PathBuf,
+    /// Serializes the read-modify-write cycles of the pipeline stages
+    lock: Arc<Mutex<()>>,
+}
+
+impl DeadLetterStore {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn list(&self) -> Result<Vec<DeadLetter>> {
+        let _guard = self.lock();
+        self.read()
+    }
+
+    /// Record a dead letter, replacing any previous one for the same job
+    pub fn push(&self, letter: DeadLetter) -> Result<()> {
+        let _guard = self.lock();
+        let mut letters = self.read()?;
+        letters
+            .retain(|l| (l.block_number, l.log_index) != (letter.block_number, letter.log_index));
+        letters.push(letter);
+        letters.sort_by_key(|l| (l.block_number, l.log_index));
+        self.write(&letters)
+    }
+
+    pub fn remove(&self, block_number: u64, log_index: u64) -> Result<()> {
+        let _guard = self.lock();
+        let mut letters = self.read()?;
+        letters.retain(|l| (l.block_number, l.log_index) != (block_number, log_index));
+        self.write(&letters)
+    }
+
+    /// The lock only serializes file accesses, a panic while holding it
+    /// leaves nothing inconsistent
+    fn lock(&self) -> MutexGuard<'_, ()> {
+        self.lock.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn read(&self) -> Result<Vec<DeadLetter>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let content = fs::read_to_string(&self.path)
+            .wrap_err_with(|| format!("Failed to read dead letters {}", self.path.display()))?;
+        serde_json::from_str(&content)
+            .wrap_err_with(|| format!("Invalid dead letters {}", self.path.display()))
+    }
+
+    fn write(&self, letters: &[DeadLetter]) -> Result<()> {
+        write_atomic(&self.path, &serde_json::to_vec_pretty(letters)?)
+            .wrap_err("Failed to save the dead letters")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use starknet::core::types::Felt;
+
+    use super::*;
+    use crate::error::{InputError, PublisherError};
+
+    #[test]
+    fn delay_doubles_up_to_max() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(10),
+        };
+
+        assert_eq!(policy.delay(1), Duration::from_secs(2));
+        assert_eq!(policy.delay(2), Duration::from_secs(4));
+        assert_eq!(policy.delay(3), Duration::from_secs(8));
+        assert_eq!(policy.delay(4), Duration::from_secs(10));
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn invalid_input_is_permanent() {
+        let error = eyre::Report::from(RelayerError::from(InputError::RootMismatch {
+            block_number: 1,
+            expected_from: "postRoot",
+            expected: U256::from(1),
+            proven: U256::from(2),
+        }));
+
+        assert_eq!(ErrorClass::of(&error), ErrorClass::Permanent);
+    }
+
+    #[test]
+    fn timeout_is_transient() {
+        let error = eyre::Report::from(RelayerError::Timeout(Duration::from_secs(1)));
+
+        assert_eq!(ErrorClass::of(&error), ErrorClass::Transient);
+    }
+
+    #[test]
+    fn contract_rejection_is_permanent() {
+        let error = eyre::Report::from(RelayerError::from(PublisherError::Reverted {
+            destination: "sepolia".into(),
+            tx_hash: Felt::ONE,
+            reason: "Failed to verify proof".into(),
+        }))
+        .wrap_err("Delivery to sepolia failed");
+
+        assert_eq!(ErrorClass::of(&error), ErrorClass::Permanent);
+    }
+
+    #[test]
+    fn node_failure_is_transient() {
+        let error = eyre::Report::from(RelayerError::from(PublisherError::Provider {
+            destination: "sepolia".into(),
+            source: "connection reset by peer".into(),
+        }));
+
+        assert_eq!(ErrorClass::of(&error), ErrorClass::Transient);
+        assert_eq!(
+            ErrorClass::of(&eyre::eyre!("connection reset by peer")),
+            ErrorClass::Transient
+        );
+    }
+
+    fn letter(block_number: u64, log_index: u64) -> DeadLetter {
+        let record = JobRecord::new(
+            block_number,
+            log_index,
+            Some(U256::from(log_index)),
+            Some(Checkpoint {
+                block_number,
+                log_index,
+            }),
+        );
+        let failure = Failure {
+            error: eyre::eyre!("connection reset by peer"),
+            class: ErrorClass::Transient,
+            attempts: 5,
+        };
+
+        DeadLetter::new(&record, RelayStage::Fetch, &failure)
+    }
+
+    #[test]
+    fn dead_letters_are_kept_per_job() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = DeadLetterStore::new(dir.path().join("dead-letters.json"));
+
+        store.push(letter(10, 3)).unwrap();
+        store.push(letter(10, BLOCK_STATE)).unwrap();
+        store.push(letter(9, 1)).unwrap();
+        store.push(letter(10, 3)).unwrap();
+
+        let keys = |store: &DeadLetterStore| {
+            store
+                .list()
+                .unwrap()
+                .iter()
+                .map(|l| (l.block_number, l.log_index, l.root))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            keys(&store),
+            [
+                (9, 1, Some(U256::from(1))),
+                (10, 3, Some(U256::from(3))),
+                (10, BLOCK_STATE, Some(U256::from(BLOCK_STATE))),
+            ]
+        );
+
+        store.remove(10, 3).unwrap();
+        assert_eq!(
+            keys(&store),
+            [
+                (9, 1, Some(U256::from(1))),
+                (10, BLOCK_STATE, Some(U256::from(BLOCK_STATE))),
+            ]
+        );
+    }
+}
@@ -0,0 +1,25 @@
+use std::{
+    fs,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use eyre::{Result, WrapErr};
+
+/// Atomically replace the content of a file by writing a temporary sibling
+/// and renaming it over the destination
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents).wrap_err_with(|| format!("Failed to write {}", tmp.display()))?;
+    fs::rename(&tmp, path).wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Current Unix timestamp in seconds
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}